@@ -1,16 +1,59 @@
+use std::collections::HashMap;
 use std::fmt::{Debug, Display};
+use std::path::Path;
+use std::sync::Arc;
 
-use crate::{parse::Rule, tree::Tree};
+use crate::{
+    mcfunction::{MCFunctionID, ScoreboardOperation},
+    parse::Rule,
+    tree::{NodeId, Tree},
+};
 use pest::iterators::Pair;
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct AST {
     pub tree: Tree<ASTNode>,
+
+    /// Every variable in the program, including compiler-generated temporaries
     pub variables: Vec<Variable>,
+
+    /// Every function-like body in the program (functions, mcfunctions, event handlers, migrations and global initializers)
+    pub functions: Vec<Function>,
+
+    /// Every source file in the program, starting with the main file
+    pub modules: Vec<Module>,
+
+    /// Indices into `functions` of the function called by every function call node
+    pub call_targets: HashMap<NodeId, usize>,
+
+    /// Indices into `variables` for every identifier and variable declaration node
+    pub node_vars: HashMap<NodeId, usize>,
+
+    /// Objective names of declared triggers, by MCFL name
+    pub triggers: HashMap<String, String>,
+
+    /// Indices into `variables` of every global variable, by MCFL name
+    pub globals: HashMap<String, usize>,
 }
 
 impl AST {
     pub fn new(tree: Tree<ASTNode>) -> AST {
-        AST { tree, variables: Vec::new() }
+        AST {
+            tree,
+            variables: Vec::new(),
+            functions: Vec::new(),
+            modules: Vec::new(),
+            call_targets: HashMap::new(),
+            node_vars: HashMap::new(),
+            triggers: HashMap::new(),
+            globals: HashMap::new(),
+        }
+    }
+
+    /// Add a variable to the program and return its index
+    pub fn new_variable(&mut self, var: Variable) -> usize {
+        self.variables.push(var);
+        self.variables.len() - 1
     }
 }
 
@@ -23,6 +66,7 @@ impl Debug for AST {
 
 pub struct Variable {
     pub mcfl_name: String,
+    pub var_type: VarType,
     pub location: VarLoc,
 }
 
@@ -30,9 +74,86 @@ pub enum VarLoc {
     /// A variable whose location is known at compile time.
     Named { name: String },
 
-    /// A variable stored on the stack.
-    /// These variables are still referenced by name within a stackframe.
-    Stack { stack_ref: String },
+    /// The score of the executing player on a trigger objective.
+    Trigger { objective: String },
+}
+
+/// A source file, whose functions are separate from those of other files
+pub struct Module {
+    /// Name other modules use to call this module's functions, such as `util` in `util::double()`
+    pub name: String,
+
+    /// The `Module` node this was declared by
+    pub node: NodeId,
+
+    /// Segment added to the start of the IDs of this module's functions. The main module has none
+    pub prefix: Option<String>,
+
+    /// Indices into `AST::functions` by MCFL name. Event handlers have no name and aren't included
+    pub functions: HashMap<String, usize>,
+
+    /// Indices into `AST::modules` of the modules this one imports, by name
+    pub imports: HashMap<String, usize>,
+
+    /// Values of the constants declared outside of any function, by name
+    pub constants: HashMap<String, i32>,
+}
+
+/// A function-like body which compiles to its own `.mcfunction` file
+pub struct Function {
+    /// Name used to refer to this function in error messages
+    pub name: String,
+
+    /// The `Function`, `MCFunction`, `EventHandler`, `Migration` or `GlobalDeclaration` node this was declared by
+    pub node: NodeId,
+
+    /// Index into `AST::modules` of the module this was declared in
+    pub module: usize,
+
+    /// Whether other modules can call this function
+    pub public: bool,
+
+    pub kind: FunctionKind,
+
+    /// Indices into `AST::variables` of this function's parameters, in order
+    pub params: Vec<usize>,
+
+    pub return_type: Option<VarType>,
+
+    /// Index into `AST::variables` of the variable this function's return value is written to
+    pub return_var: Option<usize>,
+
+    pub calling_convention: CallingConvention,
+
+    /// ID of the compiled `.mcfunction` file
+    pub id: MCFunctionID,
+
+    /// Variables in this function's scope, by MCFL name
+    pub scope: HashMap<String, usize>,
+
+    /// Values of the constants declared in this function, by name
+    pub constants: HashMap<String, i32>,
+}
+
+/// How arguments are passed to a function
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallingConvention {
+    /// The caller copies each argument into its parameter's score
+    Scores,
+
+    /// The caller stores the arguments in data storage and passes them as macro arguments, which the function copies into its parameters' scores
+    Macro,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FunctionKind {
+    Static,
+    MCFunction,
+    EventHandler,
+    Migration,
+
+    /// The initializer of a global variable declared outside of any function, which is run by the load function
+    GlobalInitializer,
 }
 
 pub struct ASTNode {
@@ -41,10 +162,10 @@ pub struct ASTNode {
 }
 
 impl ASTNode {
-    pub fn new(node_type: ASTNodeType, pair: Pair<Rule>) -> ASTNode {
+    pub fn new(node_type: ASTNodeType, pair: Pair<Rule>, file: &Path) -> ASTNode {
         ASTNode {
             node_type,
-            context: StringContext::new(pair),
+            context: StringContext::new(pair, file),
         }
     }
 }
@@ -56,22 +177,41 @@ impl Debug for ASTNode {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ASTNodeType {
     Program,
+    Module {
+        name: String,
+    },
+    Import {
+        path: String,
+
+        /// The `Module` node of the imported file, filled in once it has been parsed
+        module: Option<NodeId>,
+    },
     Function {
+        public: bool,
         name: String,
         params: Vec<VariableDeclaration>,
         return_type: Option<VarType>,
     },
     MCFunction {
         name: String,
+        advancement: Option<AdvancementTrigger>,
     },
     Block,
     VariableDeclaration {
         declaration: VariableDeclaration,
     },
+    /// A name for the value of a constant int expression, which is its only child
+    ConstDeclaration {
+        name: String,
+    },
     Assignment,
+    /// An assignment which applies `operation` to a variable's own value, such as `x += 2` or `x++`. Its children are the variable and the operand, which is 1 for `++` and `--`
+    CompoundAssignment {
+        operation: ScoreboardOperation,
+    },
     Identifier {
         id: String,
     },
@@ -83,15 +223,78 @@ pub enum ASTNodeType {
     Multiply,
     Divide,
     Modulo,
+    /// The negation of its only child
+    Negate,
     ReturnStatement,
     FunctionCall {
         id: String,
     },
+    TriggerDeclaration {
+        name: String,
+    },
+    /// A global variable declared outside of any function, whose only child is a block containing the declaration
+    GlobalDeclaration,
+    Schedule {
+        ticks: i32,
+    },
+    Sleep {
+        ticks: i32,
+    },
+    EventHandler {
+        event: Event,
+    },
+    /// A block run once by the load function when the stored state was saved by a version of the program older than `version`
+    Migration {
+        version: i32,
+    },
 }
 
-#[derive(Debug, Clone, Copy)]
+/// An in-game event which can be handled with `on <event> { ... }`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A player used `/trigger` on the named trigger objective
+    Trigger { trigger: String },
+
+    /// A player died
+    Death,
+
+    /// A player joined the game, either for the first time since the datapack was installed or after leaving
+    Join,
+
+    /// A player respawned after dying
+    Respawn,
+
+    /// A player killed an entity
+    Kill,
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::Trigger { trigger } => write!(f, "trigger({})", trigger),
+            Event::Death => write!(f, "death"),
+            Event::Join => write!(f, "join"),
+            Event::Respawn => write!(f, "respawn"),
+            Event::Kill => write!(f, "kill"),
+        }
+    }
+}
+
+/// An advancement trigger which runs an mcfunction as its reward
+#[derive(Debug, Clone)]
+pub struct AdvancementTrigger {
+    /// The trigger's ID, such as `minecraft:placed_block`
+    pub trigger: String,
+
+    /// JSON object of the trigger's conditions
+    pub conditions: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ScopeModifier {
     Default,
+
+    /// A variable that keeps its value when the datapack is reloaded or updated, so its initializer only runs if it has no value yet
     Global,
 }
 
@@ -108,7 +311,7 @@ impl std::fmt::Display for VarType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct VariableDeclaration {
     pub scope_modifier: ScopeModifier,
     pub name: String,
@@ -118,6 +321,9 @@ pub struct VariableDeclaration {
 /// The string context of an AST node.
 #[derive(Clone)]
 pub struct StringContext {
+    /// Path of the source file containing the node
+    file: Arc<Path>,
+
     /// Line number of the start of the node
     line: usize,
 
@@ -125,14 +331,15 @@ pub struct StringContext {
     col: usize,
 
     /// Line of input string containing the start of this node
-    line_str: String,
+    line_str: Arc<str>,
 
     /// Input substring making up this node
-    node_str: String,
+    #[allow(dead_code)]
+    node_str: Arc<str>,
 }
 
 impl StringContext {
-    pub fn new(pair: Pair<Rule>) -> StringContext {
+    pub fn new(pair: Pair<Rule>, file: &Path) -> StringContext {
         let node_str = pair.as_str();
         let pos = match pair.tokens().next().unwrap() {
             pest::Token::Start { rule: _, pos } => pos,
@@ -141,10 +348,11 @@ impl StringContext {
         let (line, col) = pos.line_col();
 
         StringContext {
+            file: file.into(),
             line,
             col,
-            line_str: pos.line_of().to_owned(),
-            node_str: node_str.to_owned(),
+            line_str: pos.line_of().into(),
+            node_str: node_str.into(),
         }
     }
 }
@@ -153,9 +361,10 @@ impl Display for StringContext {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "line {} col {}: \n{}\n{}↑ here",
+            "line {} col {} of {}: \n{}\n{}↑ here",
             self.line,
             self.col,
+            self.file.display(),
             self.line_str.trim_end(),
             " ".repeat(self.col - 1)
         )
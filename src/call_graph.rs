@@ -0,0 +1,57 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    ast::{ASTNodeType, FunctionKind, AST},
+    error::CompileError,
+};
+
+/// Which functions each function in a program calls, including calls made from its schedule blocks
+pub struct CallGraph {
+    /// Indices into `AST::functions` of the functions called by each function, by index into `AST::functions`
+    callees: Vec<BTreeSet<usize>>,
+}
+
+impl CallGraph {
+    pub fn new(ast: &AST) -> Result<CallGraph, CompileError> {
+        let mut callees = Vec::new();
+        for func in &ast.functions {
+            let mut called = BTreeSet::new();
+            for node in ast.tree.iter_subtree(func.node)? {
+                if let ASTNodeType::FunctionCall { .. } = ast.tree.get_node(node)?.node_type {
+                    called.insert(ast.call_targets[&node]);
+                }
+            }
+            callees.push(called);
+        }
+        Ok(CallGraph { callees })
+    }
+
+    /// Whether `func` can end up calling itself, directly or through other functions
+    pub fn is_recursive(&self, func: usize) -> bool {
+        self.reachable_from(self.callees[func].iter().copied())
+            .contains(&func)
+    }
+
+    /// Every function which can run when the datapack is used. Static functions can only be called by other functions, while anything else is run by the game or by generated functions
+    pub fn reachable(&self, ast: &AST) -> BTreeSet<usize> {
+        let roots = ast
+            .functions
+            .iter()
+            .enumerate()
+            .filter(|(_, func)| func.kind != FunctionKind::Static)
+            .map(|(index, _)| index);
+        self.reachable_from(roots)
+    }
+
+    /// Every function in `start`, along with every function they can call
+    fn reachable_from(&self, start: impl Iterator<Item = usize>) -> BTreeSet<usize> {
+        let mut reached = BTreeSet::new();
+        let mut to_visit: Vec<usize> = start.collect();
+        while let Some(func) = to_visit.pop() {
+            if reached.insert(func) {
+                to_visit.extend(self.callees[func].iter().copied());
+            }
+        }
+        reached
+    }
+}
@@ -1,60 +1,2140 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
-use rand::distributions::{Alphanumeric, DistString};
+use serde_json::{json, Value};
 
 use crate::{
-    ast::{ASTNode, ASTNodeType, VarType, AST}, datapack::DataPack, error::CompileError, tree::{NodeId, Tree}
+    ast::{
+        ASTNode, ASTNodeType, AdvancementTrigger, CallingConvention, Event, Function, FunctionKind,
+        Module, ScopeModifier, StringContext, VarLoc, VarType, Variable, AST,
+    },
+    call_graph::CallGraph,
+    datapack::DataPack,
+    error::CompileError,
+    events::{lower_events, DEATHS_OBJECTIVE, KILLS_OBJECTIVE, LEAVES_OBJECTIVE},
+    fold::fold_operation,
+    mcfunction::{
+        Command, CommandTarget, ExecuteCondition, ExecuteSubcommand, MCFunction, MCFunctionID,
+        ObjectiveCriteria, ScoreRange, ScoreboardCommand, ScoreboardOperation, Selector,
+        SelectorVariable, StoreDestination,
+    },
+    names::{function_path_segment, NameAllocator},
+    optimize::optimize,
+    session::CompilerSession,
+    setup::{Setup, CONSTANT_OBJECTIVE, STATE_VERSION_PLAYER},
+    tree::NodeId,
+    uninstall::{uninstall, UNINSTALL_FUNCTION},
+    version::TargetVersion,
 };
 
-lazy_static::lazy_static! {
-    static ref USED_NAMES: Vec<String> = Vec::new();
+/// Objective holding the value of every int variable
+const INT_OBJECTIVE: &str = "mcfl_ints";
+
+/// Paths within the private namespace of the generated load and tick functions
+const GENERATED_FUNCTIONS: [&str; 2] = ["load", "tick"];
+
+/// Path within the private namespace of the storage holding macro arguments, by function name
+const MACRO_ARGS_STORAGE: &str = "args";
+
+/// Objective holding the countdowns of scheduled blocks, for targets without the `schedule` command
+const SCHEDULE_OBJECTIVE: &str = "mcfl_schedule";
+
+/// Objectives created by the compiler, which a trigger can't be named after
+const COMPILER_OBJECTIVES: [&str; 6] = [
+    INT_OBJECTIVE,
+    CONSTANT_OBJECTIVE,
+    SCHEDULE_OBJECTIVE,
+    DEATHS_OBJECTIVE,
+    LEAVES_OBJECTIVE,
+    KILLS_OBJECTIVE,
+];
+
+/// Largest number of statements and expressions in the body of a function that's compiled into its callers instead of being called
+const INLINE_MAX_SIZE: usize = 16;
+
+/// Number of `loop` functions a function with a self tail call runs its body through, each running the one below it twice. The body can run 2^31 times, more than the game's limit on commands run at once, while calls are never nested more than this many levels deep
+const TAIL_CALL_LOOP_LEVELS: usize = 31;
+
+/// Options affecting how a program is compiled
+pub struct CompileOptions {
+    /// The Minecraft version the datapack will be used with
+    pub target: TargetVersion,
+
+    /// Name of the folder or archive the datapack is saved as
+    pub name: String,
+
+    /// Description shown in the game's datapack list
+    pub description: String,
+
+    /// Namespace of the program's mcfunctions and advancements
+    pub namespace: String,
+
+    /// Namespace for functions that shouldn't be called from outside the program
+    pub private_namespace: String,
+
+    /// Whether the datapack is saved as a zip archive instead of a folder
+    pub archive: bool,
+
+    /// Whether the generated `uninstall` function also disables the datapack
+    pub disable_on_uninstall: bool,
+
+    /// Whether to remove redundant commands from the generated functions. Turning this off makes the output easier to match up with the program
+    pub optimize: bool,
 }
 
-/// Generate a random name to be used for MCFL identifiers
-///
-/// Names are guaranteed to be unique
-fn random_name() -> String {
-    let mut name = "".to_string();
-    while USED_NAMES.contains(&name) {
-        name = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+impl CompileOptions {
+    /// The ID the game knows the datapack by once it's in a world's `datapacks` folder
+    pub fn pack_id(&self) -> String {
+        match self.archive {
+            true => format!("file/{}.zip", self.name),
+            false => format!("file/{}", self.name),
+        }
+    }
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            target: TargetVersion::default(),
+            name: "mcfl".to_owned(),
+            description: "Compiled from MCFL (Minecraft Function Language)".to_owned(),
+            namespace: "mcfl".to_owned(),
+            private_namespace: "mcfl_private".to_owned(),
+            archive: false,
+            disable_on_uninstall: false,
+            optimize: true,
+        }
     }
-    name
 }
 
 /// Fill the symbol tables for an AST
-fn name_analysis(ast: &mut AST) -> Result<(), CompileError> {
+fn name_analysis(ast: &mut AST, session: &mut CompilerSession) -> Result<(), CompileError> {
     ast.variables = Vec::new();
+    ast.functions = Vec::new();
+    ast.modules = Vec::new();
+    ast.call_targets = HashMap::new();
+    ast.node_vars = HashMap::new();
+    ast.triggers = HashMap::new();
+    ast.globals = HashMap::new();
+
+    let root = ast.tree.get_root()?;
+    declare_modules(ast, &ast.tree.get_children(root)?.clone())?;
+    // The state version shares the globals' objective, so a global can't be given its name
+    session.names.allocate(&[STATE_VERSION_PLAYER]);
+    declare_globals(ast, session, root)?;
+    hoist_globals(ast)?;
+
+    // Functions and triggers are declared before any bodies are analyzed so they can be used before their declaration
+    for module in 0..ast.modules.len() {
+        for item in ast.tree.get_children(ast.modules[module].node)?.clone() {
+            declare_item(ast, session, module, item)?;
+        }
+    }
+    let mut handled_events = Vec::new();
+    let mut migrations = Vec::new();
+    for module in 0..ast.modules.len() {
+        for item in ast.tree.get_children(ast.modules[module].node)?.clone() {
+            match ast.tree.get_node(item)?.node_type.clone() {
+                ASTNodeType::EventHandler { event } => {
+                    declare_handler(ast, session, module, item, event, &mut handled_events)?
+                }
+                ASTNodeType::Migration { version } => {
+                    declare_migration(ast, session, module, item, version, &mut migrations)?
+                }
+                _ => (),
+            }
+        }
+    }
+
+    for func in 0..ast.functions.len() {
+        let body = ast.tree.get_only_child(ast.functions[func].node)?;
+        analyze_block(ast, session, func, body)?;
 
-    fn analyze(ast: &mut AST, node: NodeId) -> Result<(), CompileError> {
-        match &ast.tree.get_node(node)?.node_type {
-            ASTNodeType::Program => todo!(),
-            ASTNodeType::Function { name, params, return_type } => todo!(),
-            ASTNodeType::MCFunction { name } => todo!(),
-            ASTNodeType::Block => todo!(),
-            ASTNodeType::VariableDeclaration { declaration } => todo!(),
-            ASTNodeType::Assignment => todo!(),
-            ASTNodeType::Identifier { id } => todo!(),
-            ASTNodeType::NumberLiteral { value } => todo!(),
-            ASTNodeType::Add => todo!(),
-            ASTNodeType::Subtract => todo!(),
-            ASTNodeType::Multiply => todo!(),
-            ASTNodeType::Divide => todo!(),
-            ASTNodeType::Modulo => todo!(),
-            ASTNodeType::ReturnStatement => todo!(),
-            ASTNodeType::FunctionCall { id } => todo!(),
+        if ast.functions[func].return_type.is_some()
+            && ast
+                .tree
+                .find_child(body, &|_, n| {
+                    matches!(n.node_type, ASTNodeType::ReturnStatement)
+                })?
+                .is_none()
+        {
+            return Err(CompileError::NoReturnStatement {
+                func_name: ast.functions[func].name.clone(),
+                context: ast.tree.get_node(ast.functions[func].node)?.context.clone(),
+            });
         }
+    }
+
+    return Ok(());
+
+    /// Add every module to the AST, giving each imported module a unique prefix for its functions' IDs
+    fn declare_modules(ast: &mut AST, nodes: &[NodeId]) -> Result<(), CompileError> {
+        let mut prefixes = NameAllocator::new(None);
+        // Generated functions are at the top level of the private namespace, so modules can't use their names
+        for reserved in
+            GENERATED_FUNCTIONS
+                .into_iter()
+                .chain(["trigger", "event", "migration", "global"])
+        {
+            prefixes.allocate(&[reserved]);
+        }
+
+        for (index, &node) in nodes.iter().enumerate() {
+            let name = match &ast.tree.get_node(node)?.node_type {
+                ASTNodeType::Module { name } => name.clone(),
+                node_type => unreachable!("{:?}", node_type),
+            };
+            let prefix = if index == 0 {
+                None
+            } else {
+                Some(prefixes.allocate(&[&function_path_segment(&name)]))
+            };
+            ast.modules.push(Module {
+                name,
+                node,
+                prefix,
+                functions: HashMap::new(),
+                imports: HashMap::new(),
+                constants: HashMap::new(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Get the ID of a function in `module`, whose path within the module is `path`
+    fn module_function_id(
+        ast: &AST,
+        module: usize,
+        namespace: &str,
+        path: &[&str],
+    ) -> MCFunctionID {
+        let mut full_path: Vec<&str> = ast.modules[module]
+            .prefix
+            .iter()
+            .map(String::as_str)
+            .collect();
+        full_path.extend(path);
+        MCFunctionID::new(namespace, &full_path)
+    }
+
+    fn declare_item(
+        ast: &mut AST,
+        session: &mut CompilerSession,
+        module: usize,
+        node: NodeId,
+    ) -> Result<(), CompileError> {
+        let ast_node = ast.tree.get_node(node)?;
+        let context = ast_node.context.clone();
+        match ast_node.node_type.clone() {
+            ASTNodeType::Import {
+                module: imported, ..
+            } => {
+                let imported = ast
+                    .modules
+                    .iter()
+                    .position(|m| Some(m.node) == imported)
+                    .unwrap();
+                let name = ast.modules[imported].name.clone();
+                if !is_identifier(&name) {
+                    return Err(CompileError::InvalidModuleName { name, context });
+                }
+                if ast.modules[module].imports.contains_key(&name) {
+                    return Err(CompileError::DuplicateImport { name, context });
+                }
+                ast.modules[module].imports.insert(name, imported);
+                Ok(())
+            }
+            ASTNodeType::Function {
+                public,
+                name,
+                params,
+                return_type,
+            } => {
+                let segment = function_path_segment(&name);
+                if ast.modules[module].prefix.is_none()
+                    && GENERATED_FUNCTIONS.contains(&segment.as_str())
+                {
+                    return Err(CompileError::ReservedFunctionName { name, context });
+                }
+                let id = module_function_id(
+                    ast,
+                    module,
+                    &session.options.private_namespace,
+                    &[&segment],
+                );
+                let prefix = ast.modules[module].prefix.clone();
+                let var_path = |var: &str| -> Vec<String> {
+                    prefix
+                        .iter()
+                        .cloned()
+                        .chain([name.clone(), var.to_owned()])
+                        .collect()
+                };
+                let mut scope = HashMap::new();
+                let mut param_vars = Vec::new();
+                for param in params {
+                    if scope.contains_key(&param.name) {
+                        return Err(CompileError::DuplicateParamName {
+                            func_name: name,
+                            param_name: param.name,
+                            context,
+                        });
+                    }
+                    let var_name = session.names.allocate(&var_path(&param.name));
+                    let var = ast.new_variable(Variable {
+                        mcfl_name: param.name.clone(),
+                        var_type: param.var_type,
+                        location: VarLoc::Named { name: var_name },
+                    });
+                    scope.insert(param.name, var);
+                    param_vars.push(var);
+                }
+                let return_var = return_type.map(|var_type| {
+                    let var_name = session.names.allocate(&var_path("return"));
+                    ast.new_variable(Variable {
+                        mcfl_name: format!("{}.return", name),
+                        var_type,
+                        location: VarLoc::Named { name: var_name },
+                    })
+                });
+
+                declare_function(
+                    ast,
+                    Function {
+                        name,
+                        node,
+                        module,
+                        public,
+                        kind: FunctionKind::Static,
+                        params: param_vars,
+                        return_type,
+                        return_var,
+                        calling_convention: CallingConvention::Scores,
+                        id,
+                        scope,
+                        constants: HashMap::new(),
+                    },
+                )
+            }
+            ASTNodeType::MCFunction { name, .. } => {
+                // mcfunctions are called by name from outside the program, so their names can't be changed to fit
+                let id = module_function_id(ast, module, &session.options.namespace, &[&name]);
+                if !id.is_valid() {
+                    return Err(CompileError::InvalidMCFunctionName { name, context });
+                }
+                if ast.modules[module].prefix.is_none() && name == UNINSTALL_FUNCTION {
+                    return Err(CompileError::ReservedMCFunctionName { name, context });
+                }
+                declare_function(
+                    ast,
+                    Function {
+                        name,
+                        node,
+                        module,
+                        public: false,
+                        kind: FunctionKind::MCFunction,
+                        params: Vec::new(),
+                        return_type: None,
+                        return_var: None,
+                        calling_convention: CallingConvention::Scores,
+                        id,
+                        scope: HashMap::new(),
+                        constants: HashMap::new(),
+                    },
+                )
+            }
+            ASTNodeType::GlobalDeclaration => {
+                let declaration = ast
+                    .tree
+                    .find_child_recursive(node, &|_, n| {
+                        matches!(n.node_type, ASTNodeType::VariableDeclaration { .. })
+                    })?
+                    .unwrap();
+                let name = match &ast.tree.get_node(declaration)?.node_type {
+                    ASTNodeType::VariableDeclaration { declaration } => declaration.name.clone(),
+                    _ => unreachable!(),
+                };
+                let id = MCFunctionID::new(
+                    &session.options.private_namespace,
+                    &["global", &function_path_segment(&name)],
+                );
+                ast.functions.push(Function {
+                    name: format!("global {}", name),
+                    node,
+                    module,
+                    public: false,
+                    kind: FunctionKind::GlobalInitializer,
+                    params: Vec::new(),
+                    return_type: None,
+                    return_var: None,
+                    calling_convention: CallingConvention::Scores,
+                    id,
+                    scope: HashMap::new(),
+                    constants: HashMap::new(),
+                });
+                Ok(())
+            }
+            ASTNodeType::ConstDeclaration { name, .. } => {
+                if ast.modules[module].constants.contains_key(&name) {
+                    return Err(CompileError::VariableAlreadyDeclared { var: name, context });
+                }
+                let value = evaluate_constant(ast, None, module, ast.tree.get_only_child(node)?)?;
+                ast.modules[module].constants.insert(name, value);
+                Ok(())
+            }
+            ASTNodeType::TriggerDeclaration { name } => {
+                if ast.triggers.contains_key(&name) {
+                    return Err(CompileError::VariableAlreadyDeclared { var: name, context });
+                }
+                if COMPILER_OBJECTIVES.contains(&name.as_str()) {
+                    return Err(CompileError::ReservedTriggerName { name, context });
+                }
+                // Players type trigger names into chat, so they can't be changed to fit either
+                let target = session.options.target;
+                if let Some(max_len) = target.max_objective_len() {
+                    if name.len() > max_len {
+                        return Err(CompileError::TriggerNameTooLong {
+                            name,
+                            max_len,
+                            target,
+                            context,
+                        });
+                    }
+                }
+                ast.triggers.insert(name.clone(), name);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Move every global declared inside a function out to the top level of its module, so that it's initialized by the load function like a global declared there
+    fn hoist_globals(ast: &mut AST) -> Result<(), CompileError> {
+        for module in 0..ast.modules.len() {
+            let module_node = ast.modules[module].node;
+            let mut statements = Vec::new();
+            for item in ast.tree.get_children(module_node)?.clone() {
+                if let ASTNodeType::GlobalDeclaration = ast.tree.get_node(item)?.node_type {
+                    continue;
+                }
+                let declarations = ast.tree.find_children_recursive(item, &|_, node| {
+                    matches!(
+                        &node.node_type,
+                        ASTNodeType::VariableDeclaration { declaration }
+                            if declaration.scope_modifier == ScopeModifier::Global
+                    )
+                })?;
+                for declaration in declarations {
+                    // A declaration with an initial value is the left hand side of an assignment
+                    let parent = ast.tree.get_parent(declaration)?;
+                    statements.push(match ast.tree.get_node(parent)?.node_type {
+                        ASTNodeType::Assignment => parent,
+                        _ => declaration,
+                    });
+                }
+            }
+
+            for statement in statements {
+                let context = ast.tree.get_node(statement)?.context.clone();
+                let global = ast.tree.new_node(ASTNode {
+                    node_type: ASTNodeType::GlobalDeclaration,
+                    context: context.clone(),
+                });
+                let block = ast.tree.new_node(ASTNode {
+                    node_type: ASTNodeType::Block,
+                    context,
+                });
+                ast.tree.detach(statement)?;
+                ast.tree.append_to(block, statement)?;
+                ast.tree.append_to(global, block)?;
+                ast.tree.append_to(module_node, global)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add every global variable to the AST, wherever it's declared, so that any function can use it before its declaration
+    fn declare_globals(
+        ast: &mut AST,
+        session: &mut CompilerSession,
+        root: NodeId,
+    ) -> Result<(), CompileError> {
+        let declarations = ast.tree.find_children_recursive(root, &|_, node| {
+            matches!(
+                &node.node_type,
+                ASTNodeType::VariableDeclaration { declaration }
+                    if declaration.scope_modifier == ScopeModifier::Global
+            )
+        })?;
+        for node in declarations {
+            let ast_node = ast.tree.get_node(node)?;
+            let declaration = match &ast_node.node_type {
+                ASTNodeType::VariableDeclaration { declaration } => declaration.clone(),
+                _ => unreachable!(),
+            };
+            if ast.globals.contains_key(&declaration.name) {
+                return Err(CompileError::VariableAlreadyDeclared {
+                    var: declaration.name,
+                    context: ast_node.context.clone(),
+                });
+            }
+            // Globals are shared by every module, and their names can't start with a function's ID
+            let name = session.names.allocate(&[format!("#{}", declaration.name)]);
+            let var = ast.new_variable(Variable {
+                mcfl_name: declaration.name.clone(),
+                var_type: declaration.var_type,
+                location: VarLoc::Named { name },
+            });
+            ast.globals.insert(declaration.name, var);
+        }
+        Ok(())
+    }
+
+    fn declare_function(ast: &mut AST, func: Function) -> Result<(), CompileError> {
+        let functions = &mut ast.modules[func.module].functions;
+        if functions.contains_key(&func.name) {
+            return Err(CompileError::FunctionAlreadyDeclared {
+                name: func.name,
+                context: ast.tree.get_node(func.node)?.context.clone(),
+            });
+        }
+        functions.insert(func.name.clone(), ast.functions.len());
+        ast.functions.push(func);
+        Ok(())
+    }
+
+    fn declare_migration(
+        ast: &mut AST,
+        session: &CompilerSession,
+        module: usize,
+        node: NodeId,
+        version: i32,
+        declared_versions: &mut Vec<i32>,
+    ) -> Result<(), CompileError> {
+        let context = ast.tree.get_node(node)?.context.clone();
+        // The stored version is never below 0, so a migration to version 0 could never run
+        if version < 1 {
+            return Err(CompileError::InvalidMigrationVersion { version, context });
+        }
+        if declared_versions.contains(&version) {
+            return Err(CompileError::DuplicateMigration { version, context });
+        }
+        declared_versions.push(version);
+
+        ast.functions.push(Function {
+            name: format!("migration({})", version),
+            node,
+            module,
+            public: false,
+            kind: FunctionKind::Migration,
+            params: Vec::new(),
+            return_type: None,
+            return_var: None,
+            calling_convention: CallingConvention::Scores,
+            id: MCFunctionID::new(
+                &session.options.private_namespace,
+                &["migration", &version.to_string()],
+            ),
+            scope: HashMap::new(),
+            constants: HashMap::new(),
+        });
+        Ok(())
+    }
+
+    fn declare_handler(
+        ast: &mut AST,
+        session: &CompilerSession,
+        module: usize,
+        node: NodeId,
+        event: Event,
+        handled_events: &mut Vec<Event>,
+    ) -> Result<(), CompileError> {
+        let context = ast.tree.get_node(node)?.context.clone();
+        if handled_events.contains(&event) {
+            return Err(CompileError::DuplicateEventHandler { event, context });
+        }
+        handled_events.push(event.clone());
+
+        let mut scope = HashMap::new();
+        let id = match &event {
+            Event::Trigger { trigger } => {
+                let objective = match ast.triggers.get(trigger) {
+                    Some(objective) => objective.clone(),
+                    None => {
+                        return Err(CompileError::UnknownTrigger {
+                            name: trigger.clone(),
+                            context,
+                        })
+                    }
+                };
+                let var = ast.new_variable(Variable {
+                    mcfl_name: trigger.clone(),
+                    var_type: VarType::Int,
+                    location: VarLoc::Trigger {
+                        objective: objective.clone(),
+                    },
+                });
+                scope.insert(trigger.clone(), var);
+                MCFunctionID::new(
+                    &session.options.private_namespace,
+                    &["trigger", &function_path_segment(&objective)],
+                )
+            }
+            _ => MCFunctionID::new(
+                &session.options.private_namespace,
+                &["event", &event.to_string()],
+            ),
+        };
+
+        ast.functions.push(Function {
+            name: format!("on {}", event),
+            node,
+            module,
+            public: false,
+            kind: FunctionKind::EventHandler,
+            params: Vec::new(),
+            return_type: None,
+            return_var: None,
+            calling_convention: CallingConvention::Scores,
+            id,
+            scope,
+            constants: HashMap::new(),
+        });
+        Ok(())
+    }
+
+    fn analyze_block(
+        ast: &mut AST,
+        session: &mut CompilerSession,
+        func: usize,
+        block: NodeId,
+    ) -> Result<(), CompileError> {
+        for statement in ast.tree.get_children(block)?.clone() {
+            analyze_statement(ast, session, func, statement)?;
+        }
+        Ok(())
+    }
+
+    fn analyze_statement(
+        ast: &mut AST,
+        session: &mut CompilerSession,
+        func: usize,
+        node: NodeId,
+    ) -> Result<(), CompileError> {
+        let ast_node = ast.tree.get_node(node)?;
+        let context = ast_node.context.clone();
+        match ast_node.node_type.clone() {
+            ASTNodeType::VariableDeclaration { declaration } => {
+                if is_declared(&ast.functions[func], &declaration.name) {
+                    return Err(CompileError::VariableAlreadyDeclared {
+                        var: declaration.name,
+                        context,
+                    });
+                }
+                if declaration.scope_modifier == ScopeModifier::Global {
+                    let var = ast.globals[&declaration.name];
+                    ast.functions[func].scope.insert(declaration.name, var);
+                    ast.node_vars.insert(node, var);
+                    return Ok(());
+                }
+                let mut path = ast.functions[func].id.path.clone();
+                path.push(declaration.name.clone());
+                let name = session.names.allocate(&path);
+                let var = ast.new_variable(Variable {
+                    mcfl_name: declaration.name.clone(),
+                    var_type: declaration.var_type,
+                    location: VarLoc::Named { name },
+                });
+                ast.functions[func].scope.insert(declaration.name, var);
+                ast.node_vars.insert(node, var);
+            }
+            ASTNodeType::ConstDeclaration { name, .. } => {
+                if is_declared(&ast.functions[func], &name) {
+                    return Err(CompileError::VariableAlreadyDeclared { var: name, context });
+                }
+                let module = ast.functions[func].module;
+                let value =
+                    evaluate_constant(ast, Some(func), module, ast.tree.get_only_child(node)?)?;
+                ast.functions[func].constants.insert(name, value);
+            }
+            ASTNodeType::Assignment | ASTNodeType::CompoundAssignment { .. } => {
+                let lhs = ast.tree.get_first_child(node)?;
+                let rhs = ast.tree.get_last_child(node)?;
+
+                // The right hand side is analyzed first so that `int a = a;` doesn't compile
+                let received = analyze_expression(ast, func, rhs)?;
+                analyze_statement(ast, session, func, lhs)?;
 
+                let var = &ast.variables[ast.node_vars[&lhs]];
+                if var.var_type != received {
+                    return Err(CompileError::MismatchedAssignmentType {
+                        var_id: var.mcfl_name.clone(),
+                        expected: var.var_type,
+                        received,
+                        context,
+                    });
+                }
+
+                if let ASTNodeType::CompoundAssignment {
+                    operation: ScoreboardOperation::Division | ScoreboardOperation::Modulo,
+                } = ast.tree.get_node(node)?.node_type
+                {
+                    if let ASTNodeType::NumberLiteral { value: 0 } =
+                        ast.tree.get_node(rhs)?.node_type
+                    {
+                        return Err(CompileError::DivisionByZero { context });
+                    }
+                }
+            }
+            ASTNodeType::Identifier { id } => {
+                let function = &ast.functions[func];
+                if !function.scope.contains_key(&id)
+                    && constant_value(ast, Some(func), function.module, &id).is_some()
+                {
+                    return Err(CompileError::AssignToConstant { name: id, context });
+                }
+                analyze_expression(ast, func, node)?;
+            }
+            ASTNodeType::Schedule { ticks } => {
+                if ticks <= 0 {
+                    return Err(CompileError::ScheduleForCurrentTick { context });
+                }
+                analyze_block(ast, session, func, ast.tree.get_only_child(node)?)?;
+            }
+            ASTNodeType::Sleep { ticks } => {
+                if ticks <= 0 {
+                    return Err(CompileError::ScheduleForCurrentTick { context });
+                }
+                // A static function's caller expects it to have finished when it returns
+                if ast.functions[func].kind == FunctionKind::Static && !in_schedule(ast, node)? {
+                    return Err(CompileError::SleepInStaticFunction {
+                        func_name: ast.functions[func].name.clone(),
+                        context,
+                    });
+                }
+                // The rest of the handler would run without its player, once for every player who was waiting
+                if ast.functions[func].kind == FunctionKind::EventHandler {
+                    return Err(CompileError::SleepInEventHandler { context });
+                }
+            }
+            ASTNodeType::ReturnStatement => {
+                // A scheduled block runs after its function has already returned
+                if in_schedule(ast, node)? {
+                    return Err(CompileError::AttemptedIllegalReturn { context });
+                }
+
+                let function = &ast.functions[func];
+                let func_name = function.name.clone();
+                let return_type = function.return_type;
+                match (ast.tree.get_children(node)?.first().cloned(), return_type) {
+                    (Some(expr), Some(expected)) => {
+                        let received = analyze_expression(ast, func, expr)?;
+                        if received != expected {
+                            return Err(CompileError::MismatchedReturnType {
+                                func_name,
+                                expected,
+                                received,
+                                context,
+                            });
+                        }
+                    }
+                    (Some(_), None) => {
+                        return Err(CompileError::ReturnFromVoid { func_name, context })
+                    }
+                    (None, Some(_)) => {
+                        return Err(CompileError::EmptyReturnStatement { func_name, context })
+                    }
+                    (None, None) => {}
+                }
+            }
+            ASTNodeType::FunctionCall { id } => {
+                analyze_call(ast, func, node, &id)?;
+            }
+            _ => unreachable!("{:?}", ast_node),
+        }
         Ok(())
     }
 
-    analyze(ast, ast.tree.get_root()?)
+    /// Whether a node is inside a scheduled block
+    fn in_schedule(ast: &AST, node: NodeId) -> Result<bool, CompileError> {
+        Ok(ast.tree.iter_ascend(node)?.any(|n| {
+            matches!(
+                ast.tree.get_node(n).map(|n| &n.node_type),
+                Ok(ASTNodeType::Schedule { .. })
+            )
+        }))
+    }
+
+    /// Resolve the names in an expression and return its type
+    fn analyze_expression(
+        ast: &mut AST,
+        func: usize,
+        node: NodeId,
+    ) -> Result<VarType, CompileError> {
+        let ast_node = ast.tree.get_node(node)?;
+        let context = ast_node.context.clone();
+        match ast_node.node_type.clone() {
+            ASTNodeType::Identifier { id } => {
+                let module = ast.functions[func].module;
+                if let Some(&var) = ast.functions[func].scope.get(&id) {
+                    // A scheduled block runs without the player whose trigger score it would use
+                    if let VarLoc::Trigger { .. } = ast.variables[var].location {
+                        if in_schedule(ast, node)? {
+                            return Err(CompileError::TriggerInSchedule { name: id, context });
+                        }
+                    }
+                    ast.node_vars.insert(node, var);
+                    Ok(ast.variables[var].var_type)
+                } else if let Some(value) = constant_value(ast, Some(func), module, &id) {
+                    ast.tree.get_node_mut(node)?.node_type = ASTNodeType::NumberLiteral { value };
+                    Ok(VarType::Int)
+                } else if let Some(var) = ast.globals.get(&id) {
+                    ast.node_vars.insert(node, *var);
+                    Ok(ast.variables[*var].var_type)
+                } else {
+                    Err(CompileError::VariableNotDeclared {
+                        var_name: id,
+                        context,
+                    })
+                }
+            }
+            ASTNodeType::NumberLiteral { .. } => Ok(VarType::Int),
+            operation @ (ASTNodeType::Add
+            | ASTNodeType::Subtract
+            | ASTNodeType::Multiply
+            | ASTNodeType::Divide
+            | ASTNodeType::Modulo) => {
+                let operands = ast.tree.get_children(node)?.clone();
+                let mut values = Vec::new();
+                for &operand in &operands {
+                    analyze_expression(ast, func, operand)?;
+                    if let ASTNodeType::NumberLiteral { value } =
+                        ast.tree.get_node(operand)?.node_type
+                    {
+                        values.push(value);
+                    }
+                }
+
+                // Operations on constants are done at compile time
+                if let [lhs, rhs] = values[..] {
+                    let value = fold_operation(&operation, lhs, rhs)
+                        .ok_or(CompileError::DivisionByZero { context })?;
+                    ast.tree.remove_children(node)?;
+                    ast.tree.get_node_mut(node)?.node_type = ASTNodeType::NumberLiteral { value };
+                } else if matches!(operation, ASTNodeType::Divide | ASTNodeType::Modulo)
+                    && matches!(
+                        ast.tree.get_node(operands[1])?.node_type,
+                        ASTNodeType::NumberLiteral { value: 0 }
+                    )
+                {
+                    return Err(CompileError::DivisionByZero { context });
+                }
+                Ok(VarType::Int)
+            }
+            ASTNodeType::Negate => {
+                let operand = ast.tree.get_only_child(node)?;
+                analyze_expression(ast, func, operand)?;
+                if let ASTNodeType::NumberLiteral { value } = ast.tree.get_node(operand)?.node_type
+                {
+                    ast.tree.remove_children(node)?;
+                    ast.tree.get_node_mut(node)?.node_type = ASTNodeType::NumberLiteral {
+                        value: value.wrapping_neg(),
+                    };
+                }
+                Ok(VarType::Int)
+            }
+            ASTNodeType::FunctionCall { id } => match analyze_call(ast, func, node, &id)? {
+                Some(return_type) => Ok(return_type),
+                None => Err(CompileError::UsingVoidReturn {
+                    func_name: id,
+                    context,
+                }),
+            },
+            _ => unreachable!("{:?}", ast_node),
+        }
+    }
+
+    /// Check the arguments to a function call and return the called function's return type
+    fn analyze_call(
+        ast: &mut AST,
+        func: usize,
+        node: NodeId,
+        id: &str,
+    ) -> Result<Option<VarType>, CompileError> {
+        let context = ast.tree.get_node(node)?.context.clone();
+        let callee = resolve_function(ast, func, id, &context)?;
+        ast.call_targets.insert(node, callee);
+
+        let args = ast.tree.get_children(node)?.clone();
+        let params = ast.functions[callee].params.clone();
+        if args.len() != params.len() {
+            return Err(CompileError::MismatchedParamCount {
+                func_name: id.to_owned(),
+                expected: params.len(),
+                received: args.len(),
+                context,
+            });
+        }
+        for (arg_index, (arg, param)) in args.into_iter().zip(params).enumerate() {
+            let received = analyze_expression(ast, func, arg)?;
+            let param_var = &ast.variables[param];
+            if received != param_var.var_type {
+                return Err(CompileError::MismatchedParamType {
+                    func_name: id.to_owned(),
+                    expected: param_var.var_type,
+                    received,
+                    arg_index,
+                    arg_name: param_var.mcfl_name.clone(),
+                    context: ast.tree.get_node(arg)?.context.clone(),
+                });
+            }
+        }
+
+        Ok(ast.functions[callee].return_type)
+    }
+
+    /// Whether a variable or constant named `name` has been declared in a function
+    fn is_declared(function: &Function, name: &str) -> bool {
+        function.scope.contains_key(name) || function.constants.contains_key(name)
+    }
+
+    /// Find the value of the constant `name`, looking in `func` before its module
+    fn constant_value(ast: &AST, func: Option<usize>, module: usize, name: &str) -> Option<i32> {
+        func.and_then(|func| ast.functions[func].constants.get(name))
+            .or_else(|| ast.modules[module].constants.get(name))
+            .copied()
+    }
+
+    /// Evaluate the initializer of a constant, which may only use literals and other constants
+    fn evaluate_constant(
+        ast: &AST,
+        func: Option<usize>,
+        module: usize,
+        node: NodeId,
+    ) -> Result<i32, CompileError> {
+        let ast_node = ast.tree.get_node(node)?;
+        match &ast_node.node_type {
+            ASTNodeType::NumberLiteral { value } => Ok(*value),
+            ASTNodeType::Identifier { id } => {
+                constant_value(ast, func, module, id).ok_or_else(|| CompileError::NotConstant {
+                    context: ast_node.context.clone(),
+                })
+            }
+            operation @ (ASTNodeType::Add
+            | ASTNodeType::Subtract
+            | ASTNodeType::Multiply
+            | ASTNodeType::Divide
+            | ASTNodeType::Modulo) => {
+                let lhs = evaluate_constant(ast, func, module, ast.tree.get_first_child(node)?)?;
+                let rhs = evaluate_constant(ast, func, module, ast.tree.get_last_child(node)?)?;
+                fold_operation(operation, lhs, rhs).ok_or_else(|| CompileError::DivisionByZero {
+                    context: ast_node.context.clone(),
+                })
+            }
+            ASTNodeType::Negate => {
+                let operand = evaluate_constant(ast, func, module, ast.tree.get_only_child(node)?)?;
+                Ok(operand.wrapping_neg())
+            }
+            _ => Err(CompileError::NotConstant {
+                context: ast_node.context.clone(),
+            }),
+        }
+    }
+
+    /// Find the function called by `id` from `func`: either `name` for a function in the same module, or `module::name` for a public function in a module it imports
+    fn resolve_function(
+        ast: &AST,
+        func: usize,
+        id: &str,
+        context: &StringContext,
+    ) -> Result<usize, CompileError> {
+        let module = &ast.modules[ast.functions[func].module];
+        let unknown = || CompileError::UnknownFunction {
+            name: id.to_owned(),
+            context: context.clone(),
+        };
+        match id.split_once("::") {
+            None => module.functions.get(id).copied().ok_or_else(unknown),
+            Some((module_name, name)) => {
+                let imported = match module.imports.get(module_name) {
+                    Some(imported) => &ast.modules[*imported],
+                    None => {
+                        return Err(CompileError::UnknownModule {
+                            name: module_name.to_owned(),
+                            context: context.clone(),
+                        })
+                    }
+                };
+                let callee = *imported.functions.get(name).ok_or_else(unknown)?;
+                if !ast.functions[callee].public {
+                    return Err(CompileError::PrivateFunction {
+                        name: id.to_owned(),
+                        context: context.clone(),
+                    });
+                }
+                Ok(callee)
+            }
+        }
+    }
+}
+
+/// Whether `name` could be written as an identifier in MCFL
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
 }
 
 /// Generate a datapack from an AST (abstract syntax tree)
-pub fn compile(mut ast: AST) -> Result<DataPack, CompileError> {
-    name_analysis(&mut ast)?;
+pub fn compile(mut ast: AST, session: &mut CompilerSession) -> Result<DataPack, CompileError> {
+    name_analysis(&mut ast, session)?;
+    let target = session.options.target;
+
+    let entry_points = ["tick", "startup"];
+    let has_entry_point = ast.functions.iter().any(|func| match func.kind {
+        FunctionKind::MCFunction => {
+            entry_points.contains(&func.name.as_str()) || advancement_trigger(&ast, func).is_some()
+        }
+        FunctionKind::EventHandler | FunctionKind::Migration => true,
+        FunctionKind::Static | FunctionKind::GlobalInitializer => false,
+    });
+    if !has_entry_point {
+        return Err(CompileError::NoEntryPoint {});
+    }
+
+    let options = &session.options;
+    let mut datapack = DataPack::new(
+        &options.name,
+        &options.namespace,
+        &options.private_namespace,
+        &options.description,
+        target,
+    );
+    let [load, tick] = GENERATED_FUNCTIONS;
+    let load_id = MCFunctionID::new(&options.private_namespace, &[load]);
+    let tick_id = MCFunctionID::new(&options.private_namespace, &[tick]);
+
+    // Small functions are compiled into their callers, and functions which can never run aren't compiled at all
+    let mut inlined = HashSet::new();
+    let mut reachable: BTreeSet<usize> = (0..ast.functions.len()).collect();
+    if options.optimize {
+        let graph = CallGraph::new(&ast)?;
+        for func in 0..ast.functions.len() {
+            if is_inlinable(&ast, &graph, func)? {
+                inlined.insert(func);
+            }
+        }
+        reachable = graph.reachable(&ast);
+    }
+
+    let mut generated = Generated {
+        session,
+        setup: Setup::new(INT_OBJECTIVE),
+        tick: MCFunction::new(),
+        functions: Vec::new(),
+        function_names: NameAllocator::new(None),
+        tail_call_flags: HashMap::new(),
+        temporaries: HashSet::new(),
+        inlined,
+    };
+    generated
+        .setup
+        .add_objective(INT_OBJECTIVE, ObjectiveCriteria::Dummy);
+
+    // Static functions take their parameters as macro arguments on targets that support them
+    if target.has_macros() {
+        for func in &mut ast.functions {
+            if func.kind == FunctionKind::Static && !func.params.is_empty() {
+                func.calling_convention = CallingConvention::Macro;
+            }
+        }
+    }
 
-    println!("{:?}", ast);
+    let mut initializers = Vec::new();
+    for func in 0..ast.functions.len() {
+        if generated.inlined.contains(&func) || !reachable.contains(&func) {
+            continue;
+        }
+        let mcfunction = compile_function(&mut ast, func, &mut generated)?;
+        let function = &ast.functions[func];
+        if function.kind == FunctionKind::GlobalInitializer {
+            initializers.push((function.module, mcfunction));
+            continue;
+        }
+        datapack.add_function(&function.id, mcfunction)?;
+
+        if let ASTNodeType::Migration { version } = ast.tree.get_node(function.node)?.node_type {
+            generated.setup.add_migration(version, function.id.clone());
+        }
+
+        if let Some(trigger) = advancement_trigger(&ast, function) {
+            let context = &ast.tree.get_node(function.node)?.context;
+            datapack.add_advancement(&function.id, advancement(trigger, &function.id, context)?);
+        }
+    }
+    for module in initialization_order(&ast) {
+        for (_, initializer) in initializers.extract_if(.., |(m, _)| *m == module) {
+            generated.setup.add_initializer(initializer);
+        }
+    }
+    lower_events(&ast, &mut generated.setup, &mut generated.tick)?;
+
+    if let Some(max_len) = target.max_objective_len() {
+        for id in generated.setup.objectives() {
+            if id.len() > max_len {
+                return Err(CompileError::ObjectiveNameTooLong {
+                    name: id.to_owned(),
+                    target,
+                });
+            }
+        }
+    }
+
+    for (id, mcfunction) in generated.functions {
+        datapack.add_function(&id, mcfunction)?;
+    }
+
+    if !generated.tick.is_empty() {
+        datapack.add_function(&tick_id, generated.tick)?;
+        datapack.tick_functions.push(tick_id);
+    }
+    for user_tick in mcfunctions_named(&ast, "tick") {
+        datapack.tick_functions.push(user_tick.id.clone());
+    }
+
+    // Generated last so that it can undo what every other function sets up
+    let options = &generated.session.options;
+    let pack_id = options.disable_on_uninstall.then(|| options.pack_id());
+    let uninstall = uninstall(&datapack, &generated.setup, pack_id.as_deref());
+    datapack.add_function(
+        &MCFunctionID::new(&options.namespace, &[UNINSTALL_FUNCTION]),
+        uninstall,
+    )?;
+
+    datapack.add_function(&load_id, generated.setup.load_function())?;
+    datapack.load_functions.push(load_id);
+    for startup in mcfunctions_named(&ast, "startup") {
+        datapack.load_functions.push(startup.id.clone());
+    }
+
+    if generated.session.options.optimize {
+        for mcfunction in datapack.functions_mut() {
+            optimize(mcfunction, &generated.temporaries);
+        }
+    }
+
+    Ok(datapack)
+}
+
+/// Functions and commands generated alongside the program's own functions
+struct Generated<'a> {
+    /// The compilation's options and name allocator
+    session: &'a mut CompilerSession,
+
+    /// Objectives and scores set up once by the load function when the datapack is loaded
+    setup: Setup,
+
+    /// Commands run every tick, before the program's `tick` function
+    tick: MCFunction,
+
+    /// Functions split off from the bodies of other functions, such as scheduled blocks
+    functions: Vec<(MCFunctionID, MCFunction)>,
+
+    /// Paths of the functions split off from others, which are numbered within the function they're split from
+    function_names: NameAllocator,
+
+    /// Name of the score each function with a self tail call sets to run its body again, by index into `AST::functions`
+    tail_call_flags: HashMap<usize, String>,
+
+    /// Names of the fake players allocated for temporary values
+    temporaries: HashSet<String>,
+
+    /// Indices into `AST::functions` of the functions whose calls are replaced by their bodies
+    inlined: HashSet<usize>,
+}
+
+/// Whether calls to `func` should be replaced by its body, which is only done for small static functions that don't call themselves
+fn is_inlinable(ast: &AST, graph: &CallGraph, func: usize) -> Result<bool, CompileError> {
+    let function = &ast.functions[func];
+    if function.kind != FunctionKind::Static || graph.is_recursive(func) {
+        return Ok(false);
+    }
 
-    todo!()
+    let mut size = 0;
+    // The function's own node and its block aren't part of its body
+    for node in ast.tree.iter_subtree(function.node)?.skip(2) {
+        match ast.tree.get_node(node)?.node_type {
+            // Scheduled blocks are split off into functions of their own, which read the function's variables after it has returned
+            ASTNodeType::Schedule { .. } | ASTNodeType::Sleep { .. } => return Ok(false),
+            _ => size += 1,
+        }
+    }
+    Ok(size <= INLINE_MAX_SIZE)
+}
+
+/// Order modules so that each one comes after every module it imports, and so its globals can be initialized using theirs
+fn initialization_order(ast: &AST) -> Vec<usize> {
+    let mut order = Vec::new();
+    for module in 0..ast.modules.len() {
+        visit(ast, module, &mut order);
+    }
+    return order;
+
+    fn visit(ast: &AST, module: usize, order: &mut Vec<usize>) {
+        if order.contains(&module) {
+            return;
+        }
+        let mut imports: Vec<usize> = ast.modules[module].imports.values().copied().collect();
+        imports.sort();
+        for imported in imports {
+            visit(ast, imported, order);
+        }
+        order.push(module);
+    }
+}
+
+/// Find the mcfunctions with an MCFL name in every module, in module order
+fn mcfunctions_named<'a>(ast: &'a AST, name: &'a str) -> impl Iterator<Item = &'a Function> {
+    ast.functions
+        .iter()
+        .filter(move |func| func.kind == FunctionKind::MCFunction && func.name == name)
+}
+
+/// Get the advancement trigger an mcfunction is the reward of, if any
+fn advancement_trigger<'a>(ast: &'a AST, func: &Function) -> Option<&'a AdvancementTrigger> {
+    match &ast.tree.get_node(func.node).ok()?.node_type {
+        ASTNodeType::MCFunction { advancement, .. } => advancement.as_ref(),
+        _ => None,
+    }
+}
+
+/// Generate a hidden advancement which is granted by `trigger` and rewards `reward`
+fn advancement(
+    trigger: &AdvancementTrigger,
+    reward: &MCFunctionID,
+    context: &StringContext,
+) -> Result<Value, CompileError> {
+    let mut criterion = json!({ "trigger": trigger.trigger });
+    if let Some(conditions) = &trigger.conditions {
+        let conditions: Value = match serde_json::from_str(conditions) {
+            Ok(conditions) => conditions,
+            Err(err) => {
+                return Err(CompileError::InvalidAdvancementConditions {
+                    reason: err.to_string(),
+                    context: context.clone(),
+                })
+            }
+        };
+        if !conditions.is_object() {
+            return Err(CompileError::InvalidAdvancementConditions {
+                reason: format!("found {}", conditions),
+                context: context.clone(),
+            });
+        }
+        criterion["conditions"] = conditions;
+    }
+
+    Ok(json!({
+        "criteria": { "trigger": criterion },
+        "rewards": { "function": reward.to_string() }
+    }))
+}
+
+/// Compile the function at index `func` of `ast.functions`
+fn compile_function(
+    ast: &mut AST,
+    func: usize,
+    generated: &mut Generated,
+) -> Result<MCFunction, CompileError> {
+    let mut mcfunction = MCFunction::new();
+    let node = ast.functions[func].node;
+
+    if ast.functions[func].calling_convention == CallingConvention::Macro {
+        for &param in &ast.functions[func].params {
+            let (target, objective) = score(ast, param);
+            mcfunction.new_command(Command::Macro {
+                template: format!(
+                    "scoreboard players set {} {} $({})",
+                    target, objective, ast.variables[param].mcfl_name
+                ),
+            });
+        }
+    }
+
+    // Advancements are revoked as soon as they're granted so that they can be granted again
+    if advancement_trigger(ast, &ast.functions[func]).is_some() {
+        mcfunction.new_command(Command::AdvancementRevoke {
+            target: Selector::new(SelectorVariable::Executor).into(),
+            advancement: ast.functions[func].id.to_string(),
+        });
+    }
+
+    let body = ast.tree.get_only_child(node)?;
+    if has_self_tail_call(ast, func)? {
+        compile_tail_call_loop(ast, func, body, &mut mcfunction, generated)?;
+    } else {
+        compile_block(ast, func, body, &mut mcfunction, generated)?;
+    }
+    Ok(mcfunction)
+}
+
+/// Compile the body of `func`, which has a self tail call, as a loop.
+///
+/// The body is split off into a function of its own, where the tail call sets a flag instead of calling `func`. Functions can't loop, so the body is run by `TAIL_CALL_LOOP_LEVELS` functions, each running the one below it twice for as long as the flag is set. Running the body any number of times then only nests calls a fixed number of levels deep
+fn compile_tail_call_loop(
+    ast: &mut AST,
+    func: usize,
+    body: NodeId,
+    mcfunction: &mut MCFunction,
+    generated: &mut Generated,
+) -> Result<(), CompileError> {
+    let mut flag_path = ast.functions[func].id.path.clone();
+    flag_path.push("tail_call".to_owned());
+    let flag = generated.session.names.allocate(&flag_path);
+    generated.tail_call_flags.insert(func, flag.clone());
+    let run_while_flag_set = |function: &MCFunctionID| Command::Execute {
+        subcommands: vec![ExecuteSubcommand::If {
+            condition: ExecuteCondition::ScoreMatches {
+                target: CommandTarget::Name { name: flag.clone() },
+                objective: INT_OBJECTIVE.to_owned(),
+                range: ScoreRange::exactly(1),
+            },
+        }],
+        command: Box::new(Command::Function {
+            function: function.clone(),
+        }),
+    };
+
+    let body_id = split_function_id(ast, func, "body", generated);
+    let mut split_body = MCFunction::new();
+    split_body.new_command(set_flag(&flag, 0));
+    compile_block(ast, func, body, &mut split_body, generated)?;
+
+    let mut below = body_id.clone();
+    let mut levels = Vec::new();
+    for _ in 0..TAIL_CALL_LOOP_LEVELS {
+        let id = split_function_id(ast, func, "loop", generated);
+        let mut level = MCFunction::new();
+        level.new_command(run_while_flag_set(&below));
+        level.new_command(run_while_flag_set(&below));
+        levels.push((id.clone(), level));
+        below = id;
+    }
+    generated.functions.push((body_id, split_body));
+    generated.functions.append(&mut levels);
+
+    mcfunction.new_command(set_flag(&flag, 1));
+    mcfunction.new_command(Command::Function { function: below });
+    Ok(())
+}
+
+/// A command setting the flag that runs a loop's body again
+fn set_flag(flag: &str, value: i32) -> Command {
+    ScoreboardCommand::PlayersSet {
+        target: CommandTarget::Name {
+            name: flag.to_owned(),
+        },
+        objective: INT_OBJECTIVE.to_owned(),
+        score: value,
+    }
+    .into()
+}
+
+/// Compile the statements in a block, stopping after a return statement
+fn compile_block(
+    ast: &mut AST,
+    func: usize,
+    block: NodeId,
+    mcfunction: &mut MCFunction,
+    generated: &mut Generated,
+) -> Result<(), CompileError> {
+    let statements = ast.tree.get_children(block)?.clone();
+    compile_statements(ast, func, &statements, mcfunction, generated)
+}
+
+/// Wrap a command which gives a variable its first value so that, if the variable was declared by `node` as `global`, it only runs while the variable is unset
+fn initialize(
+    ast: &AST,
+    node: NodeId,
+    var: usize,
+    command: Command,
+) -> Result<Command, CompileError> {
+    match &ast.tree.get_node(node)?.node_type {
+        ASTNodeType::VariableDeclaration { declaration }
+            if declaration.scope_modifier == ScopeModifier::Global =>
+        {
+            let (target, objective) = score(ast, var);
+            Ok(Command::Execute {
+                subcommands: vec![ExecuteSubcommand::Unless {
+                    condition: ExecuteCondition::ScoreMatches {
+                        target,
+                        objective,
+                        range: ScoreRange::any(),
+                    },
+                }],
+                command: Box::new(command),
+            })
+        }
+        _ => Ok(command),
+    }
+}
+
+/// Compile a sequence of statements, stopping after a return statement
+fn compile_statements(
+    ast: &mut AST,
+    func: usize,
+    statements: &[NodeId],
+    mcfunction: &mut MCFunction,
+    generated: &mut Generated,
+) -> Result<(), CompileError> {
+    for (index, &statement) in statements.iter().enumerate() {
+        let node_type = ast.tree.get_node(statement)?.node_type.clone();
+        match node_type {
+            ASTNodeType::VariableDeclaration { .. } => {
+                let var = ast.node_vars[&statement];
+                let (target, objective) = score(ast, var);
+                let set = ScoreboardCommand::PlayersSet {
+                    target,
+                    objective,
+                    score: 0,
+                };
+                mcfunction.new_command(initialize(ast, statement, var, set.into())?);
+            }
+            ASTNodeType::Assignment => {
+                let lhs = ast.tree.get_first_child(statement)?;
+                let var = ast.node_vars[&lhs];
+                let rhs = ast.tree.get_last_child(statement)?;
+                let (target, objective) = score(ast, var);
+
+                // A literal can't depend on the variable's old value, so it's set directly
+                if let ASTNodeType::NumberLiteral { value } = ast.tree.get_node(rhs)?.node_type {
+                    let set = ScoreboardCommand::PlayersSet {
+                        target,
+                        objective,
+                        score: value,
+                    };
+                    mcfunction.new_command(initialize(ast, lhs, var, set.into())?);
+                    continue;
+                }
+                let temp = new_temp(ast, generated);
+                compile_expression(ast, rhs, temp, mcfunction, generated)?;
+                let (source, source_objective) = score(ast, temp);
+                let assign = ScoreboardCommand::PlayersOperation {
+                    target,
+                    objective,
+                    operation: ScoreboardOperation::Assign,
+                    source,
+                    source_objective,
+                };
+                mcfunction.new_command(initialize(ast, lhs, var, assign.into())?);
+            }
+            // The variable's own score is operated on, so its old value doesn't need copying
+            ASTNodeType::CompoundAssignment { operation } => {
+                let var = ast.node_vars[&ast.tree.get_first_child(statement)?];
+                let rhs = ast.tree.get_last_child(statement)?;
+                match ast.tree.get_node(rhs)?.node_type {
+                    ASTNodeType::NumberLiteral { value } => {
+                        operate_literal(ast, var, operation, value, mcfunction, generated);
+                    }
+                    ASTNodeType::Identifier { .. } => {
+                        operate(ast, var, operation, ast.node_vars[&rhs], mcfunction);
+                    }
+                    _ => {
+                        let temp = new_temp(ast, generated);
+                        compile_expression(ast, rhs, temp, mcfunction, generated)?;
+                        operate(ast, var, operation, temp, mcfunction);
+                    }
+                }
+            }
+            // Constants are replaced by their values during name analysis
+            ASTNodeType::ConstDeclaration { .. } => (),
+            _ if is_self_tail_call(ast, func, statement)? => {
+                let call = match node_type {
+                    ASTNodeType::ReturnStatement => ast.tree.get_only_child(statement)?,
+                    _ => statement,
+                };
+                compile_tail_call(ast, func, call, mcfunction, generated)?;
+                break;
+            }
+            ASTNodeType::ReturnStatement => {
+                if let Some(expr) = ast.tree.get_children(statement)?.first().cloned() {
+                    let return_var = ast.functions[func].return_var.unwrap();
+                    compile_expression(ast, expr, return_var, mcfunction, generated)?;
+                }
+                break;
+            }
+            ASTNodeType::FunctionCall { .. } => {
+                compile_call(ast, statement, None, mcfunction, generated)?;
+            }
+            ASTNodeType::Schedule { ticks } => {
+                let id = split_function_id(ast, func, "schedule", generated);
+                let mut scheduled = MCFunction::new();
+                schedule(&id, ticks, mcfunction, &mut scheduled, generated);
+                let body = ast.tree.get_only_child(statement)?;
+                compile_block(ast, func, body, &mut scheduled, generated)?;
+                generated.functions.push((id, scheduled));
+            }
+            ASTNodeType::Sleep { ticks } => {
+                // Everything after a sleep is split off into a continuation, which is scheduled like a schedule block.
+                // Variables are already stored in scores, so they keep their values until the continuation runs
+                let id = split_function_id(ast, func, "continue", generated);
+                let mut continuation = MCFunction::new();
+                schedule(&id, ticks, mcfunction, &mut continuation, generated);
+                compile_statements(
+                    ast,
+                    func,
+                    &statements[index + 1..],
+                    &mut continuation,
+                    generated,
+                )?;
+                generated.functions.push((id, continuation));
+                break;
+            }
+            _ => unreachable!("{:?}", node_type),
+        }
+    }
+    Ok(())
+}
+
+/// Get the ID of a new function split off from `func`, such as `tick/schedule_0` for the first schedule block in `tick`.
+///
+/// Functions are numbered in the order they're split off from `func`, so editing one function doesn't rename those split off from any other
+fn split_function_id(
+    ast: &AST,
+    func: usize,
+    kind: &str,
+    generated: &mut Generated,
+) -> MCFunctionID {
+    let base = format!("{}/{}", ast.functions[func].id.path.join("/"), kind);
+    MCFunctionID {
+        namespace: generated.session.options.private_namespace.clone(),
+        path: generated
+            .function_names
+            .allocate_numbered(&base)
+            .split('/')
+            .map(str::to_owned)
+            .collect(),
+    }
+}
+
+/// Schedule `id` to run after `ticks` ticks, adding any setup it needs to `scheduled`
+fn schedule(
+    id: &MCFunctionID,
+    ticks: i32,
+    mcfunction: &mut MCFunction,
+    scheduled: &mut MCFunction,
+    generated: &mut Generated,
+) {
+    if generated.session.options.target.has_schedule() {
+        mcfunction.new_command(Command::Schedule {
+            function: id.clone(),
+            ticks,
+        });
+    } else {
+        schedule_countdown(id, ticks, mcfunction, scheduled, generated);
+    }
+}
+
+/// Schedule `id` to run after `ticks` ticks by counting down a score in the generated tick function
+fn schedule_countdown(
+    id: &MCFunctionID,
+    ticks: i32,
+    mcfunction: &mut MCFunction,
+    scheduled: &mut MCFunction,
+    generated: &mut Generated,
+) {
+    let countdown = CommandTarget::Name {
+        name: generated.session.names.allocate(&id.path),
+    };
+    let countdown_matches = |range| ExecuteSubcommand::If {
+        condition: ExecuteCondition::ScoreMatches {
+            target: countdown.clone(),
+            objective: SCHEDULE_OBJECTIVE.to_owned(),
+            range,
+        },
+    };
+
+    generated
+        .setup
+        .add_objective(SCHEDULE_OBJECTIVE, ObjectiveCriteria::Dummy);
+    generated.tick.new_command(Command::Execute {
+        subcommands: vec![countdown_matches(ScoreRange::at_least(1))],
+        command: Box::new(
+            ScoreboardCommand::PlayersRemove {
+                target: countdown.clone(),
+                objective: SCHEDULE_OBJECTIVE.to_owned(),
+                to_remove: 1,
+            }
+            .into(),
+        ),
+    });
+    generated.tick.new_command(Command::Execute {
+        subcommands: vec![countdown_matches(ScoreRange::exactly(0))],
+        command: Box::new(Command::Function {
+            function: id.clone(),
+        }),
+    });
+
+    mcfunction.new_command(
+        ScoreboardCommand::PlayersSet {
+            target: countdown.clone(),
+            objective: SCHEDULE_OBJECTIVE.to_owned(),
+            score: ticks,
+        }
+        .into(),
+    );
+    // The countdown is reset rather than left at 0 so that the function doesn't run again on the next tick
+    scheduled.new_command(
+        ScoreboardCommand::PlayersReset {
+            target: countdown,
+            objective: Some(SCHEDULE_OBJECTIVE.to_owned()),
+        }
+        .into(),
+    );
+}
+
+/// Compile an expression, storing its result in `dest`
+fn compile_expression(
+    ast: &mut AST,
+    node: NodeId,
+    dest: usize,
+    mcfunction: &mut MCFunction,
+    generated: &mut Generated,
+) -> Result<(), CompileError> {
+    let node_type = ast.tree.get_node(node)?.node_type.clone();
+    let operation = match node_type {
+        ASTNodeType::NumberLiteral { value } => {
+            let (target, objective) = score(ast, dest);
+            mcfunction.new_command(
+                ScoreboardCommand::PlayersSet {
+                    target,
+                    objective,
+                    score: value,
+                }
+                .into(),
+            );
+            return Ok(());
+        }
+        ASTNodeType::Identifier { .. } => {
+            copy(ast, dest, ast.node_vars[&node], mcfunction);
+            return Ok(());
+        }
+        ASTNodeType::FunctionCall { .. } => {
+            return compile_call(ast, node, Some(dest), mcfunction, generated);
+        }
+        // Scores can't be negated directly, so they're multiplied by -1 instead
+        ASTNodeType::Negate => {
+            compile_expression(
+                ast,
+                ast.tree.get_only_child(node)?,
+                dest,
+                mcfunction,
+                generated,
+            )?;
+            let (target, objective) = score(ast, dest);
+            let (source, source_objective) = generated.setup.add_constant(-1);
+            mcfunction.new_command(
+                ScoreboardCommand::PlayersOperation {
+                    target,
+                    objective,
+                    operation: ScoreboardOperation::Multiplication,
+                    source,
+                    source_objective,
+                }
+                .into(),
+            );
+            return Ok(());
+        }
+        ASTNodeType::Add => ScoreboardOperation::Addition,
+        ASTNodeType::Subtract => ScoreboardOperation::Subtraction,
+        ASTNodeType::Multiply => ScoreboardOperation::Multiplication,
+        ASTNodeType::Divide => ScoreboardOperation::Division,
+        ASTNodeType::Modulo => ScoreboardOperation::Modulo,
+        _ => unreachable!("{:?}", node_type),
+    };
+
+    compile_expression(
+        ast,
+        ast.tree.get_first_child(node)?,
+        dest,
+        mcfunction,
+        generated,
+    )?;
+
+    let rhs_node = ast.tree.get_last_child(node)?;
+    if let ASTNodeType::NumberLiteral { value } = ast.tree.get_node(rhs_node)?.node_type {
+        operate_literal(ast, dest, operation, value, mcfunction, generated);
+        return Ok(());
+    }
+
+    let rhs = new_temp(ast, generated);
+    compile_expression(ast, rhs_node, rhs, mcfunction, generated)?;
+    operate(ast, dest, operation, rhs, mcfunction);
+    Ok(())
+}
+
+/// Apply `operation` to `dest` with the literal `value` as the operand
+fn operate_literal(
+    ast: &AST,
+    dest: usize,
+    operation: ScoreboardOperation,
+    value: i32,
+    mcfunction: &mut MCFunction,
+    generated: &mut Generated,
+) {
+    let (target, objective) = score(ast, dest);
+    let command = match (operation, value.checked_neg()) {
+        // `add` and `remove` only take amounts of at least 0
+        (ScoreboardOperation::Addition, _) if value >= 0 => ScoreboardCommand::PlayersAdd {
+            target,
+            objective,
+            to_add: value,
+        },
+        (ScoreboardOperation::Addition, Some(to_remove)) => ScoreboardCommand::PlayersRemove {
+            target,
+            objective,
+            to_remove,
+        },
+        (ScoreboardOperation::Subtraction, _) if value >= 0 => ScoreboardCommand::PlayersRemove {
+            target,
+            objective,
+            to_remove: value,
+        },
+        (ScoreboardOperation::Subtraction, Some(to_add)) => ScoreboardCommand::PlayersAdd {
+            target,
+            objective,
+            to_add,
+        },
+        (operation, _) => {
+            let (source, source_objective) = generated.setup.add_constant(value);
+            ScoreboardCommand::PlayersOperation {
+                target,
+                objective,
+                operation,
+                source,
+                source_objective,
+            }
+        }
+    };
+    mcfunction.new_command(command.into());
+}
+
+/// Compile the function call `node`, storing its return value in `dest` if given
+fn compile_call(
+    ast: &mut AST,
+    node: NodeId,
+    dest: Option<usize>,
+    mcfunction: &mut MCFunction,
+    generated: &mut Generated,
+) -> Result<(), CompileError> {
+    let callee = ast.call_targets[&node];
+    let args = compile_args(ast, node, mcfunction, generated)?;
+    if generated.inlined.contains(&callee) {
+        return inline_call(ast, callee, args, dest, mcfunction, generated);
+    }
+    let callee_func = &ast.functions[callee];
+    match callee_func.calling_convention {
+        CallingConvention::Scores => {
+            for (&param, arg) in callee_func.params.iter().zip(args) {
+                copy(ast, param, arg, mcfunction);
+            }
+            mcfunction.new_command(Command::Function {
+                function: callee_func.id.clone(),
+            });
+        }
+        CallingConvention::Macro => {
+            let storage = format!(
+                "{}:{}",
+                generated.session.options.private_namespace, MACRO_ARGS_STORAGE
+            );
+            for (&param, arg) in callee_func.params.iter().zip(args) {
+                let (source, source_objective) = score(ast, arg);
+                mcfunction.new_command(Command::Execute {
+                    subcommands: vec![ExecuteSubcommand::StoreResult {
+                        destination: StoreDestination::StorageInt {
+                            storage: storage.clone(),
+                            path: format!(
+                                "{}.{}",
+                                macro_args_path(callee_func),
+                                ast.variables[param].mcfl_name
+                            ),
+                        },
+                    }],
+                    command: Box::new(
+                        ScoreboardCommand::PlayersGet {
+                            target: source,
+                            objective: source_objective,
+                        }
+                        .into(),
+                    ),
+                });
+            }
+            mcfunction.new_command(Command::FunctionWithStorage {
+                function: callee_func.id.clone(),
+                storage,
+                path: macro_args_path(callee_func),
+            });
+        }
+    }
+
+    if let Some(dest) = dest {
+        copy(
+            ast,
+            dest,
+            ast.functions[callee].return_var.unwrap(),
+            mcfunction,
+        );
+    }
+    Ok(())
+}
+
+/// Evaluate the arguments of the function call `node` into new temporaries
+fn compile_args(
+    ast: &mut AST,
+    node: NodeId,
+    mcfunction: &mut MCFunction,
+    generated: &mut Generated,
+) -> Result<Vec<usize>, CompileError> {
+    // Every argument is evaluated before any parameter is set, in case an argument calls the same function
+    let mut args = Vec::new();
+    for arg in ast.tree.get_children(node)?.clone() {
+        let temp = new_temp(ast, generated);
+        compile_expression(ast, arg, temp, mcfunction, generated)?;
+        args.push(temp);
+    }
+    Ok(args)
+}
+
+/// Whether `statement` is a call from `func`, a static function, to itself which is the last thing `func` does, either as a return value or as its last statement
+fn is_self_tail_call(ast: &AST, func: usize, statement: NodeId) -> Result<bool, CompileError> {
+    let function = &ast.functions[func];
+    // The only blocks inside a function's body are scheduled blocks, whose calls run in functions of their own
+    if function.kind != FunctionKind::Static
+        || ast.tree.get_parent(statement)? != ast.tree.get_only_child(function.node)?
+    {
+        return Ok(false);
+    }
+
+    let call = match ast.tree.get_node(statement)?.node_type {
+        ASTNodeType::ReturnStatement => match ast.tree.get_children(statement)?.first() {
+            Some(&expr) => expr,
+            None => return Ok(false),
+        },
+        ASTNodeType::FunctionCall { .. } if ast.tree.get_next_sibling(statement)?.is_none() => {
+            statement
+        }
+        _ => return Ok(false),
+    };
+    Ok(matches!(
+        ast.tree.get_node(call)?.node_type,
+        ASTNodeType::FunctionCall { .. }
+    ) && ast.call_targets[&call] == func)
+}
+
+/// Whether any statement in the body of `func` is a self tail call
+fn has_self_tail_call(ast: &AST, func: usize) -> Result<bool, CompileError> {
+    let body = ast.tree.get_only_child(ast.functions[func].node)?;
+    for &statement in ast.tree.get_children(body)? {
+        if is_self_tail_call(ast, func, statement)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Compile a self tail call in `func`, which copies the arguments straight into its parameters and sets the flag that runs its body again once this run of it ends. The return value of the last run is left in the return variable, so nothing needs copying after the call
+fn compile_tail_call(
+    ast: &mut AST,
+    func: usize,
+    call: NodeId,
+    mcfunction: &mut MCFunction,
+    generated: &mut Generated,
+) -> Result<(), CompileError> {
+    let args = compile_args(ast, call, mcfunction, generated)?;
+    for (param, arg) in ast.functions[func].params.clone().into_iter().zip(args) {
+        copy(ast, param, arg, mcfunction);
+    }
+    mcfunction.new_command(set_flag(&generated.tail_call_flags[&func], 1));
+    Ok(())
+}
+
+/// Compile the body of `callee` in place of a call to it, with the already evaluated arguments `args`
+fn inline_call(
+    ast: &mut AST,
+    callee: usize,
+    args: Vec<usize>,
+    dest: Option<usize>,
+    mcfunction: &mut MCFunction,
+    generated: &mut Generated,
+) -> Result<(), CompileError> {
+    // The callee's variables are moved to temporaries while its body is compiled, so that the optimizer knows they aren't read after the call
+    let function = &ast.functions[callee];
+    let mut vars: Vec<usize> = function
+        .scope
+        .values()
+        .copied()
+        .filter(|var| !ast.globals.values().any(|global| global == var))
+        .chain(function.return_var)
+        .collect();
+    vars.sort();
+    let mut locations = Vec::new();
+    for &var in &vars {
+        let name = new_temp_name(generated);
+        locations.push(std::mem::replace(
+            &mut ast.variables[var].location,
+            VarLoc::Named { name },
+        ));
+    }
+
+    for (param, arg) in ast.functions[callee].params.clone().into_iter().zip(args) {
+        copy(ast, param, arg, mcfunction);
+    }
+    let body = ast.tree.get_only_child(ast.functions[callee].node)?;
+    compile_block(ast, callee, body, mcfunction, generated)?;
+    if let Some(dest) = dest {
+        copy(
+            ast,
+            dest,
+            ast.functions[callee].return_var.unwrap(),
+            mcfunction,
+        );
+    }
+
+    for (var, location) in vars.into_iter().zip(locations) {
+        ast.variables[var].location = location;
+    }
+    Ok(())
+}
+
+/// Path within the macro argument storage of the arguments to `func`
+fn macro_args_path(func: &Function) -> String {
+    match func.id.path.as_slice() {
+        [name] => name.clone(),
+        // A quoted key keeps functions in modules from clashing with functions named after the module
+        path => format!("\"{}\"", path.join("/")),
+    }
+}
+
+/// Create a new compiler-generated variable to hold an intermediate value
+fn new_temp(ast: &mut AST, generated: &mut Generated) -> usize {
+    let name = new_temp_name(generated);
+    ast.new_variable(Variable {
+        mcfl_name: "<temporary>".to_owned(),
+        var_type: VarType::Int,
+        location: VarLoc::Named { name },
+    })
+}
+
+/// Allocate the name of a fake player holding an intermediate value
+fn new_temp_name(generated: &mut Generated) -> String {
+    let name = generated.session.names.allocate(&["tmp"]);
+    generated.temporaries.insert(name.clone());
+    name
+}
+
+/// Get the score holding a variable's value
+fn score(ast: &AST, var: usize) -> (CommandTarget, String) {
+    match &ast.variables[var].location {
+        VarLoc::Named { name } => (
+            CommandTarget::Name { name: name.clone() },
+            INT_OBJECTIVE.to_owned(),
+        ),
+        VarLoc::Trigger { objective } => (
+            Selector::new(SelectorVariable::Executor).into(),
+            objective.clone(),
+        ),
+    }
+}
+
+/// Apply `operation` to `dest` with `source` as the operand
+fn operate(
+    ast: &AST,
+    dest: usize,
+    operation: ScoreboardOperation,
+    source: usize,
+    mcfunction: &mut MCFunction,
+) {
+    let (target, objective) = score(ast, dest);
+    let (source, source_objective) = score(ast, source);
+    mcfunction.new_command(
+        ScoreboardCommand::PlayersOperation {
+            target,
+            objective,
+            operation,
+            source,
+            source_objective,
+        }
+        .into(),
+    );
+}
+
+/// Copy the value of `source` into `dest`
+fn copy(ast: &AST, dest: usize, source: usize, mcfunction: &mut MCFunction) {
+    operate(ast, dest, ScoreboardOperation::Assign, source, mcfunction);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{remove_file, write};
+
+    use super::*;
+    use crate::compile_file;
+
+    /// Compile `source` for `target`, saving it to a temporary file named after `test`
+    fn compile_source(test: &str, source: &str, target: TargetVersion) -> DataPack {
+        let file = std::env::temp_dir().join(format!("mcfl_{}_{}.mcfl", test, std::process::id()));
+        write(&file, source).unwrap();
+        let options = CompileOptions {
+            target,
+            ..CompileOptions::default()
+        };
+        let compiled = compile_file(&mut CompilerSession::new(options), &file, &[]);
+        remove_file(&file).unwrap();
+        compiled.unwrap()
+    }
+
+    /// Runs a datapack's functions, keeping only the scores of fake players
+    struct Game<'a> {
+        datapack: &'a DataPack,
+        scores: HashMap<(String, String), i32>,
+
+        /// How many more commands can run, like the game's `maxCommandChainLength`
+        commands_left: usize,
+
+        /// How many function calls the command being run is nested in
+        depth: usize,
+        max_depth: usize,
+    }
+
+    impl Game<'_> {
+        fn run(&mut self, id: &MCFunctionID) {
+            let namespace = if id.namespace == self.datapack.pub_namespace.id {
+                &self.datapack.pub_namespace
+            } else {
+                &self.datapack.private_namespace
+            };
+            self.depth += 1;
+            self.max_depth = self.max_depth.max(self.depth);
+            for command in namespace.functions[&id.path.join("/")].commands() {
+                if self.commands_left == 0 {
+                    break;
+                }
+                self.commands_left -= 1;
+                self.execute(command);
+            }
+            self.depth -= 1;
+        }
+
+        fn execute(&mut self, command: &Command) {
+            match command {
+                Command::Function { function } | Command::FunctionWithStorage { function, .. } => {
+                    self.run(function)
+                }
+                Command::Execute {
+                    subcommands,
+                    command,
+                } => {
+                    for subcommand in subcommands {
+                        let (condition, expected) = match subcommand {
+                            ExecuteSubcommand::If { condition } => (condition, true),
+                            ExecuteSubcommand::Unless { condition } => (condition, false),
+                            _ => continue,
+                        };
+                        let ExecuteCondition::ScoreMatches {
+                            target,
+                            objective,
+                            range,
+                        } = condition;
+                        let matches = self.score(target, objective).is_some_and(|score| {
+                            range.min.is_none_or(|min| score >= min)
+                                && range.max.is_none_or(|max| score <= max)
+                        });
+                        if matches != expected {
+                            return;
+                        }
+                    }
+                    self.execute(command);
+                }
+                Command::Scoreboard { command } => match command {
+                    ScoreboardCommand::PlayersSet {
+                        target,
+                        objective,
+                        score,
+                    } => self.set(target, objective, *score),
+                    ScoreboardCommand::PlayersAdd {
+                        target,
+                        objective,
+                        to_add,
+                    } => {
+                        let score = self.score(target, objective).unwrap_or(0);
+                        self.set(target, objective, score.wrapping_add(*to_add));
+                    }
+                    ScoreboardCommand::PlayersOperation {
+                        target,
+                        objective,
+                        operation,
+                        source,
+                        source_objective,
+                    } => {
+                        let lhs = self.score(target, objective).unwrap_or(0);
+                        let rhs = self.score(source, source_objective).unwrap_or(0);
+                        let result = match operation {
+                            ScoreboardOperation::Assign => rhs,
+                            ScoreboardOperation::Addition => lhs.wrapping_add(rhs),
+                            ScoreboardOperation::Subtraction => lhs.wrapping_sub(rhs),
+                            operation => panic!("unexpected operation {}", operation),
+                        };
+                        self.set(target, objective, result);
+                    }
+                    // Reading a score to store it in macro arguments, which are ignored
+                    ScoreboardCommand::PlayersGet { .. } => (),
+                    _ => panic!("unexpected command {}", command),
+                },
+                // Macro lines only read the arguments of functions called with storage
+                Command::Macro { .. } => (),
+                _ => panic!("unexpected command {}", command),
+            }
+        }
+
+        fn score(&self, target: &CommandTarget, objective: &str) -> Option<i32> {
+            self.scores
+                .get(&(target.to_string(), objective.to_owned()))
+                .copied()
+        }
+
+        fn set(&mut self, target: &CommandTarget, objective: &str, score: i32) {
+            self.scores
+                .insert((target.to_string(), objective.to_owned()), score);
+        }
+    }
+
+    #[test]
+    fn self_tail_calls_loop_without_nesting() {
+        let source = "
+            global int runs;
+            function spin(int n, int total) -> int { runs++; return spin(n + 1, total + n); }
+            mcfunction startup { int x = spin(1, 0); }
+        ";
+        for target in [TargetVersion::OLDEST, TargetVersion::new(20, 4)] {
+            let datapack = compile_source("tail_calls", source, target);
+            let mut game = Game {
+                datapack: &datapack,
+                scores: HashMap::new(),
+                commands_left: 100_000,
+                depth: 0,
+                max_depth: 0,
+            };
+            game.run(&MCFunctionID::new("mcfl", &["startup"]));
+
+            let runs = game.scores[&("#runs".to_owned(), INT_OBJECTIVE.to_owned())];
+            assert!(runs > 10_000, "only {} iterations ran", runs);
+            // startup, spin, every loop level and the body
+            assert_eq!(game.max_depth, TAIL_CALL_LOOP_LEVELS + 3);
+
+            // Each run has the arguments of the run before it
+            let n = game.scores[&("spin.n".to_owned(), INT_OBJECTIVE.to_owned())];
+            let total = game.scores[&("spin.total".to_owned(), INT_OBJECTIVE.to_owned())];
+            assert!(n == runs || n == runs + 1);
+            assert_eq!(total, (1..n).sum::<i32>());
+        }
+    }
 }
@@ -1,76 +1,267 @@
-use std::collections::HashMap;
-use std::fs::{create_dir_all, remove_dir_all, File};
+use std::collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap};
+use std::fs::{create_dir, create_dir_all, read, read_dir, remove_dir, remove_file, write, File};
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
-use serde_json::json;
+use serde_json::{json, Value};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
 
 use crate::error::CompileError;
-use crate::mcfunction::MCFunction;
+use crate::mcfunction::{MCFunction, MCFunctionID};
+use crate::version::TargetVersion;
 
 pub struct DataPack {
     pub pub_namespace: NameSpace,
     pub private_namespace: NameSpace,
     description: String,
     name: String,
+    target: TargetVersion,
+
+    /// Functions run every tick, in order, via the `minecraft:tick` tag
+    pub tick_functions: Vec<MCFunctionID>,
+
+    /// Functions run when the datapack is loaded, in order, via the `minecraft:load` tag
+    pub load_functions: Vec<MCFunctionID>,
 }
 
 impl DataPack {
-    pub fn new(name: &str, private_namespace_name: &str) -> DataPack {
+    pub fn new(
+        name: &str,
+        pub_namespace_name: &str,
+        private_namespace_name: &str,
+        description: &str,
+        target: TargetVersion,
+    ) -> DataPack {
         DataPack {
-            pub_namespace: NameSpace::new(name),
+            pub_namespace: NameSpace::new(pub_namespace_name),
             private_namespace: NameSpace::new(private_namespace_name),
-            description: "Compiled from MCFL (Minecraft Function Language)".to_owned(),
+            description: description.to_owned(),
             name: name.to_owned(),
+            target,
+            tick_functions: Vec::new(),
+            load_functions: Vec::new(),
         }
     }
 
+    /// Every function in both of this datapack's namespaces
+    pub fn functions_mut(&mut self) -> impl Iterator<Item = &mut MCFunction> {
+        self.pub_namespace
+            .functions
+            .values_mut()
+            .chain(self.private_namespace.functions.values_mut())
+    }
+
+    /// Add a function to whichever of this datapack's namespaces `id` refers to
+    pub fn add_function(
+        &mut self,
+        id: &MCFunctionID,
+        func: MCFunction,
+    ) -> Result<(), CompileError> {
+        if !id.is_valid() {
+            return Err(CompileError::InvalidFunctionID { id: id.to_string() });
+        }
+        let namespace = if id.namespace == self.pub_namespace.id {
+            &mut self.pub_namespace
+        } else if id.namespace == self.private_namespace.id {
+            &mut self.private_namespace
+        } else {
+            return Err(CompileError::UnknownFunctionID { id: id.to_string() });
+        };
+        match namespace.functions.entry(id.path.join("/")) {
+            Entry::Occupied(_) => Err(CompileError::DuplicateFunctionID { id: id.to_string() }),
+            Entry::Vacant(entry) => {
+                entry.insert(func);
+                Ok(())
+            }
+        }
+    }
+
+    /// Add an advancement to the public namespace with the same path as the function it rewards
+    pub fn add_advancement(&mut self, reward: &MCFunctionID, advancement: Value) {
+        self.pub_namespace
+            .advancements
+            .insert(reward.path.join("/"), advancement);
+    }
+
+    /// Every file in this datapack, by its `/`-separated path relative to the datapack's root.
+    ///
+    /// Sorted by path so that the datapack is always written in the same order.
+    pub fn files(&self) -> BTreeMap<String, Vec<u8>> {
+        let mut files = BTreeMap::new();
+
+        let mcmeta_content = json!({
+            "pack": {
+                "pack_format": self.target.pack_format(),
+                "description": self.description
+            }
+        });
+        files.insert(
+            "pack.mcmeta".to_owned(),
+            mcmeta_content.to_string().into_bytes(),
+        );
+
+        let tags_path = format!("data/minecraft/tags/{}", self.target.function_folder());
+        for (tag, functions) in [
+            ("tick", &self.tick_functions),
+            ("load", &self.load_functions),
+        ] {
+            if functions.is_empty() {
+                continue;
+            }
+            let values: Vec<String> = functions.iter().map(|id| id.to_string()).collect();
+            let content = json!({ "values": values });
+            files.insert(
+                format!("{}/{}.json", tags_path, tag),
+                content.to_string().into_bytes(),
+            );
+        }
+
+        self.pub_namespace.add_files(&mut files, self.target);
+        self.private_namespace.add_files(&mut files, self.target);
+
+        files
+    }
+
     /// Save this datapack to a directory.
     ///
     /// * `dest_dir` - The directory in which to write this datapack. For example, the datapacks folder for a Minecraft save.
     ///
     /// Note that the datapack will be saved to a folder whose title is the name of the datapack.
+    ///
+    /// Saving is incremental: files whose content hasn't changed are left alone, and files and directories are only deleted if an earlier save created them, as recorded in the folder's manifest. Nothing is saved if that would overwrite a file an earlier save didn't create.
     pub fn save(&self, dest_dir: &Path) -> Result<(), CompileError> {
         let path = &dest_dir.join(&self.name);
+        create_dir_all(path)?;
+
+        let manifest_path = path.join(MANIFEST_FILE);
+        let previous = SaveManifest::read(&manifest_path)?;
+        let mut manifest = SaveManifest {
+            files: BTreeSet::new(),
+            directories: previous.directories.clone(),
+        };
 
-        if path.exists() {
-            remove_dir_all(path)?;
+        let files = self.files();
+        // Otherwise the file would be listed in the manifest, and deleted by a later save
+        if let Some(unmanaged) = files
+            .keys()
+            .find(|file| !previous.files.contains(*file) && path.join(file).exists())
+        {
+            return Err(CompileError::UnmanagedFile {
+                path: path.join(unmanaged).display().to_string(),
+            });
         }
-        create_dir_all(path)?;
 
-        let mcmeta_path = path.join("pack.mcmeta");
-        let mcmeta_content = json!({
-            "pack": {
-                "pack_format": 4,
-                "description": self.description
+        for (file_path, content) in files {
+            if let Some((parent, _)) = file_path.rsplit_once('/') {
+                let mut dir = String::new();
+                for component in parent.split('/') {
+                    if !dir.is_empty() {
+                        dir.push('/');
+                    }
+                    dir.push_str(component);
+                    if !path.join(&dir).is_dir() {
+                        create_dir(path.join(&dir))?;
+                        manifest.directories.insert(dir.clone());
+                    }
+                }
+            }
+
+            let full_path = path.join(&file_path);
+            if read(&full_path).ok().as_ref() != Some(&content) {
+                write(&full_path, &content)?;
+            }
+            manifest.files.insert(file_path);
+        }
+
+        for stale in previous.files.difference(&manifest.files) {
+            let full_path = path.join(stale);
+            if full_path.is_file() {
+                remove_file(full_path)?;
             }
-        });
-        let mut mcmeta = File::create(mcmeta_path)?;
-        mcmeta.write_all(mcmeta_content.to_string().as_bytes())?;
-
-        let tags_path = path
-            .join("data")
-            .join("minecraft")
-            .join("tags")
-            .join("functions");
-        create_dir_all(&tags_path)?;
-        if self.pub_namespace.functions.contains_key("tick") {
-            let tick_path = tags_path.join("tick.json");
-            let tick_content = json!({ "values": [format!("{}:tick", self.pub_namespace.id)] });
-            let mut tick = File::create(tick_path)?;
-            tick.write_all(tick_content.to_string().as_bytes())?;
         }
-        if self.pub_namespace.functions.contains_key("startup") {
-            let startup_path = tags_path.join("load.json");
-            let startup_content =
-                json!({ "values": [format!("{}:startup", self.pub_namespace.id)] });
-            let mut startup = File::create(startup_path)?;
-            startup.write_all(startup_content.to_string().as_bytes())?;
+
+        // Children sort after their parents, so this empties directories from the bottom up
+        for dir in previous.directories.iter().rev() {
+            let full_path = path.join(dir);
+            if !full_path.is_dir() {
+                manifest.directories.remove(dir);
+            } else if read_dir(&full_path)?.next().is_none() {
+                remove_dir(full_path)?;
+                manifest.directories.remove(dir);
+            }
         }
 
-        self.pub_namespace.save(path)?;
-        self.private_namespace.save(path)?;
+        manifest.write(&manifest_path)
+    }
 
+    /// Save this datapack as a single zip archive, which Minecraft can load without unpacking.
+    ///
+    /// * `dest_dir` - The directory in which to write the archive, named after the datapack.
+    ///
+    /// Entries are written in sorted order with fixed timestamps and permissions, so compiling the same program twice produces identical archives.
+    pub fn save_zip(&self, dest_dir: &Path) -> Result<(), CompileError> {
+        create_dir_all(dest_dir)?;
+        let archive = File::create(dest_dir.join(format!("{}.zip", self.name)))?;
+
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(DateTime::default())
+            .unix_permissions(0o644);
+
+        let mut zip = ZipWriter::new(archive);
+        for (file_path, content) in self.files() {
+            zip.start_file(file_path, options)?;
+            zip.write_all(&content)?;
+        }
+        zip.finish()?;
+
+        Ok(())
+    }
+}
+
+/// Name of the file, in the root of a saved datapack, listing what the compiler wrote there
+const MANIFEST_FILE: &str = ".mcfl_manifest.json";
+
+/// The files and directories written by previous saves of a datapack, relative to its root
+struct SaveManifest {
+    files: BTreeSet<String>,
+    directories: BTreeSet<String>,
+}
+
+impl SaveManifest {
+    /// Read the manifest at `path`, which is empty if the datapack hasn't been saved before
+    fn read(path: &Path) -> Result<SaveManifest, CompileError> {
+        let mut manifest = SaveManifest {
+            files: BTreeSet::new(),
+            directories: BTreeSet::new(),
+        };
+        if !path.exists() {
+            return Ok(manifest);
+        }
+
+        let corrupt = || CompileError::CorruptSaveManifest {
+            path: path.display().to_string(),
+        };
+        let content: Value = serde_json::from_slice(&read(path)?).map_err(|_| corrupt())?;
+        for (key, set) in [
+            ("files", &mut manifest.files),
+            ("directories", &mut manifest.directories),
+        ] {
+            let entries = content[key].as_array().ok_or_else(corrupt)?;
+            for entry in entries {
+                set.insert(entry.as_str().ok_or_else(corrupt)?.to_owned());
+            }
+        }
+        Ok(manifest)
+    }
+
+    fn write(&self, path: &Path) -> Result<(), CompileError> {
+        let content = json!({
+            "files": self.files,
+            "directories": self.directories,
+        });
+        write(path, format!("{:#}", content))?;
         Ok(())
     }
 }
@@ -78,6 +269,9 @@ impl DataPack {
 pub struct NameSpace {
     pub id: String,
     pub functions: HashMap<String, MCFunction>,
+
+    /// Advancement JSON by path within this namespace
+    pub advancements: HashMap<String, Value>,
 }
 
 impl NameSpace {
@@ -85,21 +279,131 @@ impl NameSpace {
         NameSpace {
             id: id.to_owned(),
             functions: HashMap::new(),
+            advancements: HashMap::new(),
+        }
+    }
+
+    /// Add this namespace's functions and advancements to a datapack's files
+    fn add_files(&self, files: &mut BTreeMap<String, Vec<u8>>, target: TargetVersion) {
+        for (name, func) in &self.functions {
+            files.insert(
+                format!(
+                    "data/{}/{}/{}.mcfunction",
+                    self.id,
+                    target.function_folder(),
+                    name
+                ),
+                func.to_string().into_bytes(),
+            );
+        }
+
+        for (name, advancement) in &self.advancements {
+            files.insert(
+                format!(
+                    "data/{}/{}/{}.json",
+                    self.id,
+                    target.advancement_folder(),
+                    name
+                ),
+                advancement.to_string().into_bytes(),
+            );
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+    use std::path::PathBuf;
 
-    pub fn save(&self, root: &Path) -> Result<(), CompileError> {
-        if !self.functions.is_empty() {
-            let func_root_path = root.join("data").join(&self.id).join("functions");
-            create_dir_all(&func_root_path)?;
+    use super::*;
+    use crate::mcfunction::Command;
 
-            for (name, func) in &self.functions {
-                let func_path = func_root_path.join(format!("{}.mcfunction", name));
-                let mut func_file = File::create(func_path)?;
-                func_file.write_all(format!("{}", func).as_bytes())?;
+    /// A datapack with a function for each path in `functions`, each calling the next
+    fn datapack(functions: &[&str]) -> DataPack {
+        let mut datapack = DataPack::new(
+            "pack",
+            "ns",
+            "ns_private",
+            "A pack",
+            TargetVersion::default(),
+        );
+        for (index, path) in functions.iter().enumerate() {
+            let mut func = MCFunction::new();
+            if let Some(next) = functions.get(index + 1) {
+                func.new_command(Command::Function {
+                    function: MCFunctionID::new("ns", &[next]),
+                });
             }
+            datapack
+                .add_function(&MCFunctionID::new("ns", &[path]), func)
+                .unwrap();
         }
+        datapack
+            .tick_functions
+            .push(MCFunctionID::new("ns", &[functions[0]]));
+        datapack
+    }
 
-        Ok(())
+    /// An empty directory for a test to save into
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mcfl_{}_{}", name, std::process::id()));
+        let _ = remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn zip_is_deterministic() {
+        let functions = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let first = test_dir("zip_first");
+        let second = test_dir("zip_second");
+        datapack(&functions).save_zip(&first).unwrap();
+        datapack(&functions).save_zip(&second).unwrap();
+
+        let archive = read(first.join("pack.zip")).unwrap();
+        assert!(!archive.is_empty());
+        assert_eq!(archive, read(second.join("pack.zip")).unwrap());
+        remove_dir_all(first).unwrap();
+        remove_dir_all(second).unwrap();
+    }
+
+    #[test]
+    fn save_deletes_only_stale_files() {
+        let dir = test_dir("save_stale");
+        let functions = dir.join("pack/data/ns/functions");
+        datapack(&["a", "b", "c"]).save(&dir).unwrap();
+        assert!(functions.join("c.mcfunction").is_file());
+
+        write(functions.join("notes.txt"), "mine").unwrap();
+        write(dir.join("pack/data/ns/readme.md"), "mine").unwrap();
+        datapack(&["a", "b"]).save(&dir).unwrap();
+
+        assert!(!functions.join("c.mcfunction").exists());
+        assert!(functions.join("a.mcfunction").is_file());
+        assert!(functions.join("b.mcfunction").is_file());
+        assert_eq!(read(functions.join("notes.txt")).unwrap(), b"mine");
+        assert_eq!(read(dir.join("pack/data/ns/readme.md")).unwrap(), b"mine");
+        let manifest = SaveManifest::read(&dir.join("pack").join(MANIFEST_FILE)).unwrap();
+        assert!(manifest.files.contains("data/ns/functions/a.mcfunction"));
+        assert!(!manifest.files.contains("data/ns/functions/c.mcfunction"));
+        assert!(!manifest.files.contains("data/ns/functions/notes.txt"));
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn save_refuses_to_overwrite_unmanaged_files() {
+        let dir = test_dir("save_unmanaged");
+        let functions = dir.join("pack/data/ns/functions");
+        create_dir_all(&functions).unwrap();
+        write(functions.join("b.mcfunction"), "mine").unwrap();
+
+        assert!(matches!(
+            datapack(&["a", "b"]).save(&dir),
+            Err(CompileError::UnmanagedFile { .. })
+        ));
+        assert_eq!(read(functions.join("b.mcfunction")).unwrap(), b"mine");
+        assert!(!functions.join("a.mcfunction").exists());
+        assert!(!dir.join("pack").join(MANIFEST_FILE).exists());
+        remove_dir_all(dir).unwrap();
     }
 }
@@ -1,6 +1,7 @@
-use crate::ast::{StringContext, VarType};
+use crate::ast::{Event, StringContext, VarType};
 use crate::parse::Rule;
 use crate::tree::NodeId;
+use crate::version::TargetVersion;
 use pest::error::Error;
 
 #[derive(Debug)]
@@ -13,6 +14,7 @@ pub enum TreeError {
     MismatchedTreeAndNodeID { node_id: NodeId, tree_id: usize },
 }
 
+#[allow(dead_code)]
 pub enum CompileError {
     ParseError {
         err: Box<Error<Rule>>,
@@ -23,6 +25,15 @@ pub enum CompileError {
     IOError {
         err: std::io::Error,
     },
+    ZipError {
+        err: zip::result::ZipError,
+    },
+    CorruptSaveManifest {
+        path: String,
+    },
+    UnmanagedFile {
+        path: String,
+    },
     NoEntryPoint {},
     CompilingNonMCFunction {},
     VariableAlreadyDeclared {
@@ -90,15 +101,151 @@ pub enum CompileError {
         param_name: String,
         context: StringContext,
     },
+    FunctionAlreadyDeclared {
+        name: String,
+        context: StringContext,
+    },
+    UnknownTrigger {
+        name: String,
+        context: StringContext,
+    },
+    NotConstant {
+        context: StringContext,
+    },
+    AssignToConstant {
+        name: String,
+        context: StringContext,
+    },
+    DivisionByZero {
+        context: StringContext,
+    },
+    InvalidMigrationVersion {
+        version: i32,
+        context: StringContext,
+    },
+    NumberOutOfRange {
+        context: StringContext,
+    },
+    DurationTooLong {
+        context: StringContext,
+    },
+    DuplicateMigration {
+        version: i32,
+        context: StringContext,
+    },
+    DuplicateEventHandler {
+        event: Event,
+        context: StringContext,
+    },
+    InvalidAdvancementConditions {
+        reason: String,
+        context: StringContext,
+    },
+    ScheduleForCurrentTick {
+        context: StringContext,
+    },
+    SleepInStaticFunction {
+        func_name: String,
+        context: StringContext,
+    },
+    SleepInEventHandler {
+        context: StringContext,
+    },
+    TriggerInSchedule {
+        name: String,
+        context: StringContext,
+    },
+    UnsupportedTargetVersion {
+        version: String,
+    },
+    InvalidMCFunctionName {
+        name: String,
+        context: StringContext,
+    },
+    ReservedMCFunctionName {
+        name: String,
+        context: StringContext,
+    },
+    ReservedFunctionName {
+        name: String,
+        context: StringContext,
+    },
+    ReservedTriggerName {
+        name: String,
+        context: StringContext,
+    },
+    TriggerNameTooLong {
+        name: String,
+        max_len: usize,
+        target: TargetVersion,
+        context: StringContext,
+    },
+    ObjectiveNameTooLong {
+        name: String,
+        target: TargetVersion,
+    },
+    InvalidFunctionID {
+        id: String,
+    },
+    DuplicateFunctionID {
+        id: String,
+    },
+    InvalidProjectManifest {
+        path: String,
+        reason: String,
+    },
+    SourceNotFound {
+        file: String,
+        sources: Vec<String>,
+    },
+    ReadSourceError {
+        file: String,
+        err: std::io::Error,
+    },
+    ImportNotFound {
+        path: String,
+        context: StringContext,
+    },
+    ImportCycle {
+        chain: Vec<String>,
+        context: StringContext,
+    },
+    InvalidModuleName {
+        name: String,
+        context: StringContext,
+    },
+    DuplicateImport {
+        name: String,
+        context: StringContext,
+    },
+    UnknownModule {
+        name: String,
+        context: StringContext,
+    },
+    PrivateFunction {
+        name: String,
+        context: StringContext,
+    },
 }
 
-impl std::fmt::Debug for CompileError {
+impl std::fmt::Display for CompileError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut include_pos = |ctx: &StringContext, msg: &str| write!(f, "{} on {}", msg, ctx);
         match self {
-            Self::ParseError { err } => write!(f, "ParseError: {:?}", err),
+            Self::ParseError { err } => write!(f, "Syntax error\n{}", err),
             Self::TreeError { err } => write!(f, "TreeError: {:?}", err),
-            Self::IOError { err } => write!(f, "I/O error: {:?}", err),
+            Self::IOError { err } => write!(f, "I/O error: {}", err),
+            Self::ZipError { err } => write!(f, "Zip error: {}", err),
+            Self::CorruptSaveManifest { path } => write!(
+                f,
+                "Save manifest {:?} is corrupt. Delete it to save the datapack from scratch",
+                path
+            ),
+            Self::UnmanagedFile { path } => write!(
+                f,
+                "Won't overwrite {:?} because the compiler didn't create it. Move or delete it to save the datapack",
+                path
+            ),
             Self::NoEntryPoint {} => {
                 write!(f, "No entrypoint ('tick' or 'startup' function) found")
             }
@@ -214,11 +361,185 @@ impl std::fmt::Debug for CompileError {
                     func_name,
                     param_name
                 )
-            )
+            ),
+            Self::FunctionAlreadyDeclared { name, context } => include_pos(
+                context,
+                &format!("Function {} has already been declared", name),
+            ),
+            Self::UnknownTrigger { name, context } => include_pos(
+                context,
+                &format!("Trigger {} has not been declared", name),
+            ),
+            Self::NotConstant { context } => include_pos(
+                context,
+                "Constants can only be given values made of literals and other constants",
+            ),
+            Self::AssignToConstant { name, context } => include_pos(
+                context,
+                &format!("{} is a constant, so it can't be assigned to", name),
+            ),
+            Self::DivisionByZero { context } => include_pos(context, "Division by zero"),
+            Self::NumberOutOfRange { context } => include_pos(
+                context,
+                "Number is out of range. Scores hold whole numbers from -2147483648 to 2147483647",
+            ),
+            Self::DurationTooLong { context } => include_pos(
+                context,
+                "Duration is too long. Durations can be at most 2147483647 ticks",
+            ),
+            Self::InvalidMigrationVersion { version, context } => include_pos(
+                context,
+                &format!("Migration versions start at 1, but this migration is to version {}", version),
+            ),
+            Self::DuplicateMigration { version, context } => include_pos(
+                context,
+                &format!("There is already a migration to version {}", version),
+            ),
+            Self::DuplicateEventHandler { event, context } => include_pos(
+                context,
+                &format!("A handler for event {} has already been declared", event),
+            ),
+            Self::InvalidAdvancementConditions { reason, context } => include_pos(
+                context,
+                &format!("Advancement conditions must be a JSON object: {}", reason),
+            ),
+            Self::ScheduleForCurrentTick { context } => {
+                include_pos(context, "Blocks must be scheduled at least 1 tick in the future")
+            }
+            Self::UnsupportedTargetVersion { version } => write!(
+                f,
+                "Can't compile for Minecraft version {:?}. Supported versions are {} through {}",
+                version,
+                TargetVersion::OLDEST,
+                TargetVersion::LATEST
+            ),
+            Self::SleepInStaticFunction { func_name, context } => include_pos(
+                context,
+                &format!(
+                    "Function {} cannot sleep because it isn't an mcfunction",
+                    func_name
+                ),
+            ),
+            Self::SleepInEventHandler { context } => include_pos(
+                context,
+                "Event handlers cannot sleep because the rest of the handler would run without the player it's handling",
+            ),
+            Self::TriggerInSchedule { name, context } => include_pos(
+                context,
+                &format!(
+                    "Trigger {} cannot be used in a scheduled block because the block runs without the player who used it",
+                    name
+                ),
+            ),
+            Self::InvalidMCFunctionName { name, context } => include_pos(
+                context,
+                &format!(
+                    "mcfunction {} can't be given that name in a datapack, which only allows lowercase letters, digits and underscores",
+                    name
+                ),
+            ),
+            Self::ReservedMCFunctionName { name, context } => include_pos(
+                context,
+                &format!(
+                    "mcfunction {} can't be declared because the compiler generates a function with that name",
+                    name
+                ),
+            ),
+            Self::ReservedFunctionName { name, context } => include_pos(
+                context,
+                &format!(
+                    "function {} can't be declared in the main file because the compiler generates a function with that name",
+                    name
+                ),
+            ),
+            Self::ReservedTriggerName { name, context } => include_pos(
+                context,
+                &format!(
+                    "Trigger {} can't be declared because the compiler uses an objective with that name",
+                    name
+                ),
+            ),
+            Self::TriggerNameTooLong {
+                name,
+                max_len,
+                target,
+                context,
+            } => include_pos(
+                context,
+                &format!(
+                    "Trigger {} is longer than the {} characters allowed in Minecraft {}",
+                    name, max_len, target
+                ),
+            ),
+            Self::ObjectiveNameTooLong { name, target } => write!(
+                f,
+                "Objective {:?} is too long for Minecraft {}",
+                name, target
+            ),
+            Self::InvalidFunctionID { id } => write!(
+                f,
+                "Function ID {:?} contains characters Minecraft doesn't allow",
+                id
+            ),
+            Self::DuplicateFunctionID { id } => write!(
+                f,
+                "Two functions were given the ID {:?}",
+                id
+            ),
+            Self::InvalidProjectManifest { path, reason } => {
+                write!(f, "Invalid project manifest {:?}: {}", path, reason)
+            }
+            Self::SourceNotFound { file, sources } => write!(
+                f,
+                "Couldn't find source file {:?} in any of the source directories {:?}",
+                file, sources
+            ),
+            Self::ReadSourceError { file, err } => {
+                write!(f, "Couldn't read source file {:?}: {}", file, err)
+            }
+            Self::ImportNotFound { path, context } => include_pos(
+                context,
+                &format!(
+                    "Couldn't find imported file {:?} next to the importing file or in any source directory",
+                    path
+                ),
+            ),
+            Self::ImportCycle { chain, context } => include_pos(
+                context,
+                &format!("Files import each other in a cycle: {}", chain.join(" -> ")),
+            ),
+            Self::InvalidModuleName { name, context } => include_pos(
+                context,
+                &format!(
+                    "Can't import a file named {:?} because its name isn't a valid identifier",
+                    name
+                ),
+            ),
+            Self::DuplicateImport { name, context } => include_pos(
+                context,
+                &format!("A module named {} has already been imported", name),
+            ),
+            Self::UnknownModule { name, context } => include_pos(
+                context,
+                &format!("Module {} hasn't been imported", name),
+            ),
+            Self::PrivateFunction { name, context } => include_pos(
+                context,
+                &format!(
+                    "Function {} can't be called from another module because it isn't declared `pub`",
+                    name
+                ),
+            ),
         }
     }
 }
 
+impl std::fmt::Debug for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
 impl From<TreeError> for CompileError {
     fn from(err: TreeError) -> Self {
         CompileError::TreeError { err }
@@ -236,3 +557,9 @@ impl From<std::io::Error> for CompileError {
         CompileError::IOError { err }
     }
 }
+
+impl From<zip::result::ZipError> for CompileError {
+    fn from(err: zip::result::ZipError) -> Self {
+        CompileError::ZipError { err }
+    }
+}
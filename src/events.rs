@@ -0,0 +1,161 @@
+use crate::{
+    ast::{ASTNodeType, Event, FunctionKind, AST},
+    error::CompileError,
+    mcfunction::{
+        Command, CommandTarget, ExecuteSubcommand, MCFunction, MCFunctionID, ObjectiveCriteria,
+        ScoreRange, ScoreboardCommand, Selector, SelectorArgument, SelectorVariable, TagAction,
+    },
+    setup::Setup,
+};
+
+/// Objective counting each player's deaths since the last tick
+pub const DEATHS_OBJECTIVE: &str = "mcfl_deaths";
+
+/// Objective counting how many times each player has left the game since the last tick
+pub const LEAVES_OBJECTIVE: &str = "mcfl_leaves";
+
+/// Objective counting each player's kills since the last tick
+pub const KILLS_OBJECTIVE: &str = "mcfl_kills";
+
+/// Tag given to players who have died but not yet respawned
+const DEAD_TAG: &str = "mcfl_dead";
+
+/// Tag given to every player who has joined since the datapack was installed
+const JOINED_TAG: &str = "mcfl_joined";
+
+/// Add the objectives needed to detect every handled event to `setup`, and the commands which call their handlers to `tick`
+pub fn lower_events(
+    ast: &AST,
+    setup: &mut Setup,
+    tick: &mut MCFunction,
+) -> Result<(), CompileError> {
+    let mut handlers: Vec<(Event, MCFunctionID)> = Vec::new();
+    for func in &ast.functions {
+        if func.kind != FunctionKind::EventHandler {
+            continue;
+        }
+        if let ASTNodeType::EventHandler { event } = &ast.tree.get_node(func.node)?.node_type {
+            handlers.push((event.clone(), func.id.clone()));
+        }
+    }
+    let handler = |event: &Event| {
+        handlers
+            .iter()
+            .find(|(e, _)| e == event)
+            .map(|(_, id)| id.clone())
+    };
+
+    let mut triggers: Vec<(&String, &String)> = ast.triggers.iter().collect();
+    triggers.sort();
+    for (trigger, objective) in triggers {
+        setup.add_objective(objective, ObjectiveCriteria::Trigger);
+        tick.new_command(
+            ScoreboardCommand::PlayersEnable {
+                target: Selector::new(SelectorVariable::AllPlayers).into(),
+                objective: objective.clone(),
+            }
+            .into(),
+        );
+        if let Some(id) = handler(&Event::Trigger {
+            trigger: trigger.clone(),
+        }) {
+            run_as(tick, players_with_score(objective), id);
+            clear_score(tick, objective);
+        }
+    }
+
+    let death = handler(&Event::Death);
+    let respawn = handler(&Event::Respawn);
+    if death.is_some() || respawn.is_some() {
+        setup.add_objective(DEATHS_OBJECTIVE, ObjectiveCriteria::DeathCount);
+
+        // Dead players aren't matched by `@e`, so a player with the dead tag who is found by it has respawned
+        if let Some(id) = respawn {
+            let respawned = Selector::new(SelectorVariable::AllEntities)
+                .with(SelectorArgument::Type {
+                    entity_type: "minecraft:player".to_owned(),
+                })
+                .with(SelectorArgument::Tag {
+                    tag: DEAD_TAG.to_owned(),
+                    negated: false,
+                });
+            run_as(tick, respawned.clone(), id);
+            tag(tick, respawned, TagAction::Remove, DEAD_TAG);
+            tag(
+                tick,
+                players_with_score(DEATHS_OBJECTIVE),
+                TagAction::Add,
+                DEAD_TAG,
+            );
+        }
+        if let Some(id) = death {
+            run_as(tick, players_with_score(DEATHS_OBJECTIVE), id);
+        }
+        clear_score(tick, DEATHS_OBJECTIVE);
+    }
+
+    if let Some(id) = handler(&Event::Join) {
+        setup.add_objective(
+            LEAVES_OBJECTIVE,
+            ObjectiveCriteria::Custom {
+                stat: "leave_game".to_owned(),
+            },
+        );
+
+        let first_join = Selector::new(SelectorVariable::AllPlayers).with(SelectorArgument::Tag {
+            tag: JOINED_TAG.to_owned(),
+            negated: true,
+        });
+        run_as(tick, first_join.clone(), id.clone());
+        tag(tick, first_join, TagAction::Add, JOINED_TAG);
+
+        // A player who has left the game since the last tick and is now online must have rejoined
+        run_as(tick, players_with_score(LEAVES_OBJECTIVE), id);
+        clear_score(tick, LEAVES_OBJECTIVE);
+    }
+
+    if let Some(id) = handler(&Event::Kill) {
+        setup.add_objective(KILLS_OBJECTIVE, ObjectiveCriteria::TotalKillCount);
+        run_as(tick, players_with_score(KILLS_OBJECTIVE), id);
+        clear_score(tick, KILLS_OBJECTIVE);
+    }
+
+    Ok(())
+}
+
+/// Select every player with a positive score on `objective`
+fn players_with_score(objective: &str) -> Selector {
+    Selector::new(SelectorVariable::AllPlayers).with(SelectorArgument::Scores {
+        scores: vec![(objective.to_owned(), ScoreRange::at_least(1))],
+    })
+}
+
+/// Call `function` as each entity matching `selector`
+fn run_as(tick: &mut MCFunction, selector: Selector, function: MCFunctionID) {
+    tick.new_command(Command::Execute {
+        subcommands: vec![ExecuteSubcommand::As {
+            target: selector.into(),
+        }],
+        command: Box::new(Command::Function { function }),
+    });
+}
+
+/// Set every positive score on `objective` back to 0 so the event isn't handled again
+fn clear_score(tick: &mut MCFunction, objective: &str) {
+    tick.new_command(
+        ScoreboardCommand::PlayersSet {
+            target: players_with_score(objective).into(),
+            objective: objective.to_owned(),
+            score: 0,
+        }
+        .into(),
+    );
+}
+
+fn tag(tick: &mut MCFunction, selector: Selector, action: TagAction, tag: &str) {
+    tick.new_command(Command::Tag {
+        target: CommandTarget::from(selector),
+        action,
+        tag: tag.to_owned(),
+    });
+}
@@ -0,0 +1,92 @@
+use crate::ast::ASTNodeType;
+
+/// Evaluate a binary operation on two constants the same way `scoreboard players operation` would, or return `None` when dividing by zero
+///
+/// Scores are 32-bit and wrap around on overflow, division rounds down, and the result of `%` has the sign of the divisor
+pub fn fold_operation(operation: &ASTNodeType, lhs: i32, rhs: i32) -> Option<i32> {
+    match operation {
+        ASTNodeType::Add => Some(lhs.wrapping_add(rhs)),
+        ASTNodeType::Subtract => Some(lhs.wrapping_sub(rhs)),
+        ASTNodeType::Multiply => Some(lhs.wrapping_mul(rhs)),
+        ASTNodeType::Divide => floor_div(lhs, rhs),
+        ASTNodeType::Modulo => floor_mod(lhs, rhs),
+        _ => unreachable!("{:?}", operation),
+    }
+}
+
+/// Java's `Math.floorDiv`, which rounds towards negative infinity
+fn floor_div(lhs: i32, rhs: i32) -> Option<i32> {
+    if rhs == 0 {
+        return None;
+    }
+    let quotient = lhs.wrapping_div(rhs);
+    if lhs.wrapping_rem(rhs) != 0 && ((lhs < 0) != (rhs < 0)) {
+        Some(quotient - 1)
+    } else {
+        Some(quotient)
+    }
+}
+
+/// Java's `Math.floorMod`, whose result has the sign of `rhs`
+fn floor_mod(lhs: i32, rhs: i32) -> Option<i32> {
+    if rhs == 0 {
+        return None;
+    }
+    let remainder = lhs.wrapping_rem(rhs);
+    if remainder != 0 && ((remainder < 0) != (rhs < 0)) {
+        Some(remainder + rhs)
+    } else {
+        Some(remainder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn division_rounds_down() {
+        assert_eq!(fold_operation(&ASTNodeType::Divide, 7, 2), Some(3));
+        assert_eq!(fold_operation(&ASTNodeType::Divide, -7, 2), Some(-4));
+        assert_eq!(fold_operation(&ASTNodeType::Divide, 7, -2), Some(-4));
+        assert_eq!(fold_operation(&ASTNodeType::Divide, -7, -2), Some(3));
+        assert_eq!(fold_operation(&ASTNodeType::Divide, -8, 2), Some(-4));
+    }
+
+    #[test]
+    fn modulo_takes_sign_of_divisor() {
+        assert_eq!(fold_operation(&ASTNodeType::Modulo, 7, 3), Some(1));
+        assert_eq!(fold_operation(&ASTNodeType::Modulo, -7, 3), Some(2));
+        assert_eq!(fold_operation(&ASTNodeType::Modulo, 7, -3), Some(-2));
+        assert_eq!(fold_operation(&ASTNodeType::Modulo, -7, -3), Some(-1));
+        assert_eq!(fold_operation(&ASTNodeType::Modulo, -6, 3), Some(0));
+    }
+
+    #[test]
+    fn overflow_wraps_around() {
+        assert_eq!(
+            fold_operation(&ASTNodeType::Add, i32::MAX, 1),
+            Some(i32::MIN)
+        );
+        assert_eq!(
+            fold_operation(&ASTNodeType::Subtract, i32::MIN, 1),
+            Some(i32::MAX)
+        );
+        assert_eq!(
+            fold_operation(&ASTNodeType::Multiply, 65536, 65536),
+            Some(0)
+        );
+        assert_eq!(
+            fold_operation(&ASTNodeType::Divide, i32::MIN, -1),
+            Some(i32::MIN)
+        );
+        assert_eq!(fold_operation(&ASTNodeType::Modulo, i32::MIN, -1), Some(0));
+    }
+
+    #[test]
+    fn division_by_zero_is_not_folded() {
+        assert_eq!(fold_operation(&ASTNodeType::Divide, 1, 0), None);
+        assert_eq!(fold_operation(&ASTNodeType::Modulo, 1, 0), None);
+        assert_eq!(fold_operation(&ASTNodeType::Divide, 0, 0), None);
+    }
+}
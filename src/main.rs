@@ -1,56 +1,109 @@
-use core::panic;
-use std::{fs::File, io::Read, path::Path};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 use ast::AST;
 use compile::compile;
 use datapack::DataPack;
 use error::CompileError;
 use parse::parse;
+use project::{Project, MANIFEST_FILE};
+use session::CompilerSession;
 
 extern crate pest;
 #[macro_use]
 extern crate pest_derive;
 extern crate lazy_static;
-extern crate rand;
 
 mod ast;
+mod call_graph;
 mod compile;
 mod datapack;
 mod error;
+mod events;
+mod fold;
 mod id_tracker;
 mod mcfunction;
+mod names;
+mod optimize;
 mod parse;
+mod project;
+mod session;
+mod setup;
 mod tree;
+mod uninstall;
+mod version;
 
-/// TODO:
-/// - Remove 'scoreboard objectives add mcfl_ints dummy' from non-startup functions
-/// - Make FunctionVars not have to be cloned all the time. That seems so very awful
-/// - Make it possible to save state
+// TODO:
+// - Make FunctionVars not have to be cloned all the time. That seems so very awful
 
-fn main() {
-    let compiled = compile_file("examples/test.mcfl").unwrap();
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
 }
 
-fn compile_file(file_path: &str) -> Result<DataPack, CompileError> {
-    let path = Path::new(file_path);
-    let path_display = path.display();
+/// Compile a project, described by `mcfl.toml` in the current directory if there is one, or a single file given on the command line
+///
+/// * `<file>` - Compile this file instead of the project's main file. Without a project, defaults to `examples/test.mcfl`
+/// * `--project <path>` - Use the manifest at this path instead of `mcfl.toml`
+/// * `--target <version>` - The Minecraft version to compile for, such as `1.20.4`. Overrides the manifest, and defaults to 1.13
+/// * `--zip` - Save the datapack as a zip archive instead of a folder, whatever the manifest says
+/// * `--no-optimize` - Keep every generated command, which makes the output easier to debug
+fn run() -> Result<(), CompileError> {
+    let mut file_path = None;
+    let mut manifest_path = None;
+    let mut target = None;
+    let mut archive = false;
+    let mut optimize = true;
 
-    let mut file = match File::open(path) {
-        Ok(file) => file,
-        Err(err) => panic!("Couldn't read file {}: {}", path_display, err),
-    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--project" => manifest_path = args.next().map(PathBuf::from),
+            "--target" => target = Some(args.next().unwrap_or_default().parse()?),
+            "--zip" => archive = true,
+            "--no-optimize" => optimize = false,
+            _ => file_path = Some(PathBuf::from(arg)),
+        }
+    }
 
-    let mut s = String::new();
-    match file.read_to_string(&mut s) {
-        Ok(_) => {}
-        Err(err) => panic!("Couldn't read file to string {}: {}", path_display, err),
+    let manifest_path = manifest_path.or_else(|| {
+        let default_path = PathBuf::from(MANIFEST_FILE);
+        default_path.exists().then_some(default_path)
+    });
+    let mut project = match manifest_path {
+        Some(path) => Project::load(&path)?,
+        None => Project::default(),
+    };
+    if let Some(target) = target {
+        project.options.target = target;
+    }
+    project.options.archive |= archive;
+    project.options.optimize &= optimize;
+    let file_path = match file_path {
+        Some(path) => path,
+        None => project.main_file()?,
     };
 
-    compile_string(&s)
+    let mut session = CompilerSession::new(project.options);
+    let datapack = compile_file(&mut session, &file_path, &project.sources)?;
+    if session.options.archive {
+        datapack.save_zip(&project.output)
+    } else {
+        datapack.save(&project.output)
+    }
 }
 
-fn compile_string(toparse: &str) -> Result<DataPack, CompileError> {
-    let parsed = AST::new(parse(toparse)?);
-    let compiled = compile(parsed)?;
+fn compile_file(
+    session: &mut CompilerSession,
+    path: &Path,
+    sources: &[PathBuf],
+) -> Result<DataPack, CompileError> {
+    let parsed = AST::new(parse(session, path, sources)?);
+    let compiled = compile(parsed, session)?;
     Ok(compiled)
 }
@@ -12,6 +12,23 @@ impl MCFunction {
     pub fn new_command(&mut self, command: Command) {
         self.commands.push(command)
     }
+
+    /// Add every command of `other` to the end of this function
+    pub fn append(&mut self, mut other: MCFunction) {
+        self.commands.append(&mut other.commands);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    pub fn commands_mut(&mut self) -> &mut Vec<Command> {
+        &mut self.commands
+    }
 }
 
 impl Display for MCFunction {
@@ -24,8 +41,48 @@ impl Display for MCFunction {
 }
 
 pub enum Command {
-    Scoreboard { command: ScoreboardCommand },
-    Function { function: MCFunctionID },
+    Scoreboard {
+        command: ScoreboardCommand,
+    },
+    Function {
+        function: MCFunctionID,
+    },
+    /// Call a function with the macro arguments in the compound at `path` in a storage
+    FunctionWithStorage {
+        function: MCFunctionID,
+        storage: String,
+        path: String,
+    },
+    /// A line of a function with `$(name)` placeholders that are filled in by the function's macro arguments
+    Macro {
+        template: String,
+    },
+    Execute {
+        subcommands: Vec<ExecuteSubcommand>,
+        command: Box<Command>,
+    },
+    Tag {
+        target: CommandTarget,
+        action: TagAction,
+        tag: String,
+    },
+    AdvancementRevoke {
+        target: CommandTarget,
+        advancement: String,
+    },
+    Schedule {
+        function: MCFunctionID,
+        ticks: i32,
+    },
+    /// Remove the value at an NBT path in a storage
+    DataRemoveStorage {
+        storage: String,
+        path: String,
+    },
+    /// Disable a datapack by its ID, such as `"file/mcfl"`
+    DatapackDisable {
+        pack: String,
+    },
 }
 
 impl Display for Command {
@@ -33,10 +90,162 @@ impl Display for Command {
         match self {
             Command::Scoreboard { command } => write!(f, "{}", command),
             Command::Function { function } => write!(f, "function {}", function),
+            Command::FunctionWithStorage {
+                function,
+                storage,
+                path,
+            } => write!(f, "function {} with storage {} {}", function, storage, path),
+            Command::Macro { template } => write!(f, "${}", template),
+            Command::Execute {
+                subcommands,
+                command,
+            } => {
+                write!(f, "execute")?;
+                for subcommand in subcommands {
+                    write!(f, " {}", subcommand)?;
+                }
+                write!(f, " run {}", command)
+            }
+            Command::Tag {
+                target,
+                action,
+                tag,
+            } => write!(f, "tag {} {} {}", target, action, tag),
+            Command::AdvancementRevoke {
+                target,
+                advancement,
+            } => write!(f, "advancement revoke {} only {}", target, advancement),
+            Command::Schedule { function, ticks } => {
+                write!(f, "schedule function {} {}t", function, ticks)
+            }
+            Command::DataRemoveStorage { storage, path } => {
+                write!(f, "data remove storage {} {}", storage, path)
+            }
+            Command::DatapackDisable { pack } => write!(f, "datapack disable {}", pack),
+        }
+    }
+}
+
+pub enum TagAction {
+    Add,
+    Remove,
+}
+
+impl Display for TagAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TagAction::Add => write!(f, "add"),
+            TagAction::Remove => write!(f, "remove"),
+        }
+    }
+}
+
+pub enum ExecuteSubcommand {
+    As { target: CommandTarget },
+    If { condition: ExecuteCondition },
+    Unless { condition: ExecuteCondition },
+    StoreResult { destination: StoreDestination },
+}
+
+impl Display for ExecuteSubcommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecuteSubcommand::As { target } => write!(f, "as {}", target),
+            ExecuteSubcommand::If { condition } => write!(f, "if {}", condition),
+            ExecuteSubcommand::Unless { condition } => write!(f, "unless {}", condition),
+            ExecuteSubcommand::StoreResult { destination } => {
+                write!(f, "store result {}", destination)
+            }
+        }
+    }
+}
+
+pub enum StoreDestination {
+    /// An int at an NBT path in a storage
+    StorageInt { storage: String, path: String },
+}
+
+impl Display for StoreDestination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreDestination::StorageInt { storage, path } => {
+                write!(f, "storage {} {} int 1", storage, path)
+            }
         }
     }
 }
 
+pub enum ExecuteCondition {
+    ScoreMatches {
+        target: CommandTarget,
+        objective: String,
+        range: ScoreRange,
+    },
+}
+
+impl Display for ExecuteCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecuteCondition::ScoreMatches {
+                target,
+                objective,
+                range,
+            } => write!(f, "score {} {} matches {}", target, objective, range),
+        }
+    }
+}
+
+/// An inclusive range of scores, where a missing bound is unbounded
+#[derive(Clone, Copy)]
+pub struct ScoreRange {
+    pub min: Option<i32>,
+    pub max: Option<i32>,
+}
+
+impl ScoreRange {
+    /// A range matching only `value`
+    pub fn exactly(value: i32) -> ScoreRange {
+        ScoreRange {
+            min: Some(value),
+            max: Some(value),
+        }
+    }
+
+    /// A range matching every score, so that it only fails to match a score that isn't set
+    pub fn any() -> ScoreRange {
+        ScoreRange::at_least(i32::MIN)
+    }
+
+    /// A range matching `value` and everything below it
+    pub fn at_most(value: i32) -> ScoreRange {
+        ScoreRange {
+            min: None,
+            max: Some(value),
+        }
+    }
+
+    /// A range matching `value` and everything above it
+    pub fn at_least(value: i32) -> ScoreRange {
+        ScoreRange {
+            min: Some(value),
+            max: None,
+        }
+    }
+}
+
+impl Display for ScoreRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) if min == max => write!(f, "{}", min),
+            (Some(min), Some(max)) => write!(f, "{}..{}", min, max),
+            (Some(min), None) => write!(f, "{}..", min),
+            (None, Some(max)) => write!(f, "..{}", max),
+            (None, None) => write!(f, ".."),
+        }
+    }
+}
+
+#[allow(dead_code)]
 pub enum ScoreboardCommand {
     ObjectivesAdd {
         id: String,
@@ -72,6 +281,14 @@ pub enum ScoreboardCommand {
         target: CommandTarget,
         objective: Option<String>,
     },
+    PlayersEnable {
+        target: CommandTarget,
+        objective: String,
+    },
+    PlayersGet {
+        target: CommandTarget,
+        objective: String,
+    },
 }
 
 impl From<ScoreboardCommand> for Command {
@@ -132,22 +349,42 @@ impl Display for ScoreboardCommand {
                 Some(o) => write!(f, "scoreboard players reset {} {}", target, o),
                 None => write!(f, "scoreboard players reset {}", target),
             },
+            ScoreboardCommand::PlayersEnable { target, objective } => {
+                write!(f, "scoreboard players enable {} {}", target, objective)
+            }
+            ScoreboardCommand::PlayersGet { target, objective } => {
+                write!(f, "scoreboard players get {} {}", target, objective)
+            }
         }
     }
 }
 
 pub enum ObjectiveCriteria {
     Dummy,
+    Trigger,
+    DeathCount,
+    TotalKillCount,
+
+    /// A statistic from the `minecraft.custom` category, such as `leave_game`
+    Custom {
+        stat: String,
+    },
 }
 
 impl Display for ObjectiveCriteria {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ObjectiveCriteria::Dummy => write!(f, "dummy"),
+            ObjectiveCriteria::Trigger => write!(f, "trigger"),
+            ObjectiveCriteria::DeathCount => write!(f, "deathCount"),
+            ObjectiveCriteria::TotalKillCount => write!(f, "totalKillCount"),
+            ObjectiveCriteria::Custom { stat } => write!(f, "minecraft.custom:minecraft.{}", stat),
         }
     }
 }
 
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ScoreboardOperation {
     Addition,
     Subtraction,
@@ -176,23 +413,131 @@ impl Display for ScoreboardOperation {
     }
 }
 
+#[derive(Clone)]
 pub enum CommandTarget {
     Name { name: String },
+    Selector { selector: Selector },
 }
 
 impl Display for CommandTarget {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             CommandTarget::Name { name } => write!(f, "{}", name),
+            CommandTarget::Selector { selector } => write!(f, "{}", selector),
         }
     }
 }
 
+/// A target selector such as `@a[scores={foo=1..}]`
+#[derive(Clone)]
+pub struct Selector {
+    pub variable: SelectorVariable,
+    pub arguments: Vec<SelectorArgument>,
+}
+
+impl Selector {
+    /// A selector with no arguments
+    pub fn new(variable: SelectorVariable) -> Selector {
+        Selector {
+            variable,
+            arguments: Vec::new(),
+        }
+    }
+
+    /// Add an argument to this selector
+    pub fn with(mut self, argument: SelectorArgument) -> Selector {
+        self.arguments.push(argument);
+        self
+    }
+}
+
+impl From<Selector> for CommandTarget {
+    fn from(selector: Selector) -> Self {
+        CommandTarget::Selector { selector }
+    }
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.variable)?;
+        if !self.arguments.is_empty() {
+            let args: Vec<String> = self.arguments.iter().map(|a| a.to_string()).collect();
+            write!(f, "[{}]", args.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum SelectorVariable {
+    AllPlayers,
+    AllEntities,
+    Executor,
+}
+
+impl Display for SelectorVariable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SelectorVariable::AllPlayers => write!(f, "@a"),
+            SelectorVariable::AllEntities => write!(f, "@e"),
+            SelectorVariable::Executor => write!(f, "@s"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum SelectorArgument {
+    Scores { scores: Vec<(String, ScoreRange)> },
+    Tag { tag: String, negated: bool },
+    Type { entity_type: String },
+}
+
+impl Display for SelectorArgument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SelectorArgument::Scores { scores } => {
+                let scores: Vec<String> = scores
+                    .iter()
+                    .map(|(objective, range)| format!("{}={}", objective, range))
+                    .collect();
+                write!(f, "scores={{{}}}", scores.join(","))
+            }
+            SelectorArgument::Tag { tag, negated } => {
+                write!(f, "tag={}{}", if *negated { "!" } else { "" }, tag)
+            }
+            SelectorArgument::Type { entity_type } => write!(f, "type={}", entity_type),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct MCFunctionID {
     pub namespace: String,
     pub path: Vec<String>,
 }
 
+impl MCFunctionID {
+    pub fn new(namespace: &str, path: &[&str]) -> MCFunctionID {
+        MCFunctionID {
+            namespace: namespace.to_owned(),
+            path: path.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    /// Whether Minecraft accepts this ID
+    pub fn is_valid(&self) -> bool {
+        is_valid_id_part(&self.namespace) && self.path.iter().all(|part| is_valid_id_part(part))
+    }
+}
+
+/// Whether `part` can be used as a namespace or path segment in a Minecraft ID, which may only contain lowercase letters, digits, `_`, `-` and `.`
+pub fn is_valid_id_part(part: &str) -> bool {
+    !part.is_empty()
+        && part
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | '.'))
+}
+
 impl Display for MCFunctionID {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}:{}", self.namespace, self.path.join("/"))
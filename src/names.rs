@@ -0,0 +1,96 @@
+use std::collections::{HashMap, HashSet};
+
+/// Struct to keep track of the names given to scores, so that every name is unique and the same program always gets the same names
+#[derive(Default)]
+pub struct NameAllocator {
+    /// Every name that has been handed out
+    used: HashSet<String>,
+
+    /// The last numeric suffix tried for each base name, or the next one to try for names from `allocate_numbered`
+    suffixes: HashMap<String, usize>,
+
+    /// The longest name allowed, if there is a limit
+    max_len: Option<usize>,
+}
+
+impl NameAllocator {
+    pub fn new(max_len: Option<usize>) -> NameAllocator {
+        NameAllocator {
+            max_len,
+            ..Default::default()
+        }
+    }
+
+    /// Get a name made by joining `path` with dots, such as `double.x` for the variable `x` in the function `double`.
+    ///
+    /// If that name is taken, a numeric suffix is added, giving `double.x_2` and so on. Names longer than the limit are cut short to fit
+    pub fn allocate<S: AsRef<str>>(&mut self, path: &[S]) -> String {
+        let base = path.iter().map(AsRef::as_ref).collect::<Vec<_>>().join(".");
+        let mut name = truncate(&base, "", self.max_len);
+        let suffix = self.suffixes.entry(base.clone()).or_insert(1);
+        while self.used.contains(&name) {
+            *suffix += 1;
+            name = truncate(&base, &format!("_{}", suffix), self.max_len);
+        }
+        self.used.insert(name.clone());
+        name
+    }
+
+    /// Get `base` followed by the lowest number it hasn't been given yet, such as `tick/schedule_0` and then `tick/schedule_1`.
+    ///
+    /// Unlike `allocate`, the name always has a number and is never cut short, so it can be used as part of a function's path
+    pub fn allocate_numbered(&mut self, base: &str) -> String {
+        let next = self.suffixes.entry(base.to_owned()).or_insert(0);
+        let name = loop {
+            let name = format!("{}_{}", base, next);
+            *next += 1;
+            if !self.used.contains(&name) {
+                break name;
+            }
+        };
+        self.used.insert(name.clone());
+        name
+    }
+}
+
+/// Join `base` and `suffix`, cutting the end off `base` so the result is no longer than `max_len`
+fn truncate(base: &str, suffix: &str, max_len: Option<usize>) -> String {
+    let keep = match max_len {
+        Some(max_len) => base.len().min(max_len.saturating_sub(suffix.len())),
+        None => base.len(),
+    };
+    format!("{}{}", &base[..keep], suffix)
+}
+
+/// Turn an MCFL identifier into a function path segment, which can't contain uppercase letters.
+///
+/// Each uppercase letter becomes `-` followed by the lowercase letter. Identifiers can't contain `-`, so two different identifiers never give the same segment
+pub fn function_path_segment(name: &str) -> String {
+    let mut segment = String::new();
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            segment.push('-');
+            segment.push(c.to_ascii_lowercase());
+        } else {
+            segment.push(c);
+        }
+    }
+    segment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbered_names_count_up_for_each_base() {
+        let mut names = NameAllocator::new(None);
+        assert_eq!(names.allocate_numbered("tick/schedule"), "tick/schedule_0");
+        assert_eq!(names.allocate_numbered("tick/continue"), "tick/continue_0");
+        assert_eq!(names.allocate_numbered("tick/schedule"), "tick/schedule_1");
+        assert_eq!(
+            names.allocate_numbered("startup/schedule"),
+            "startup/schedule_0"
+        );
+    }
+}
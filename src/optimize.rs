@@ -0,0 +1,772 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    mcfunction::{
+        Command, CommandTarget, ExecuteCondition, ExecuteSubcommand, MCFunction, ScoreboardCommand,
+        ScoreboardOperation,
+    },
+    setup::CONSTANT_OBJECTIVE,
+};
+
+/// Remove redundant commands from a function and replace others with cheaper ones
+///
+/// Only the scores of fake players are reasoned about. Temporaries are never used outside the function they're created in, so they are dead once the function ends, but every other score might be read by another function, including those called by this one
+///
+/// * `temporaries` - Names of the fake players holding temporary values
+pub fn optimize(mcfunction: &mut MCFunction, temporaries: &HashSet<String>) {
+    let commands = mcfunction.commands_mut();
+    loop {
+        let mut changed = propagate(commands, temporaries);
+        while coalesce(commands, temporaries) {
+            changed = true;
+        }
+        changed |= remove_dead_stores(commands, temporaries);
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// A fake player's score on an objective
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Score {
+    name: String,
+    objective: String,
+}
+
+/// What is known about a score's value at some point in a function
+#[derive(Clone, PartialEq)]
+enum Value {
+    Constant(i32),
+
+    /// The same value as another score
+    Copy(Score),
+}
+
+/// How a command uses scores
+#[derive(Default)]
+struct Effects {
+    /// Scores whose values the command uses
+    reads: Vec<Score>,
+
+    /// Scores the command sets without using their previous values
+    kills: Vec<Score>,
+
+    /// Scores the command may change while keeping some part of their previous values, or only change sometimes
+    modifies: Vec<Score>,
+
+    /// Whether the command may read or change any score other than a temporary's, such as by calling a function
+    barrier: bool,
+}
+
+impl Effects {
+    /// Whether the command uses or changes `score` in any way
+    fn touches(&self, score: &Score, temporaries: &HashSet<String>) -> bool {
+        self.reads.contains(score)
+            || self.kills.contains(score)
+            || self.modifies.contains(score)
+            || (self.barrier && !is_temporary(score, temporaries))
+    }
+}
+
+fn is_temporary(score: &Score, temporaries: &HashSet<String>) -> bool {
+    temporaries.contains(&score.name)
+}
+
+/// Get the score of a target if it's a fake player. Selectors can only match entities, which never share a score with a fake player
+fn fake_player(target: &CommandTarget, objective: &str) -> Option<Score> {
+    match target {
+        CommandTarget::Name { name } => Some(Score {
+            name: name.clone(),
+            objective: objective.to_owned(),
+        }),
+        CommandTarget::Selector { .. } => None,
+    }
+}
+
+fn effects(command: &Command) -> Effects {
+    match command {
+        Command::Scoreboard { command } => scoreboard_effects(command),
+        Command::Execute {
+            subcommands,
+            command,
+        } => {
+            let mut effects = effects(command);
+            // The command might not run, or might run more than once
+            let mut kills = std::mem::take(&mut effects.kills);
+            effects.modifies.append(&mut kills);
+            for subcommand in subcommands {
+                if let ExecuteSubcommand::If { condition }
+                | ExecuteSubcommand::Unless { condition } = subcommand
+                {
+                    let ExecuteCondition::ScoreMatches {
+                        target, objective, ..
+                    } = condition;
+                    effects.reads.extend(fake_player(target, objective));
+                }
+            }
+            effects
+        }
+        Command::Tag { .. } | Command::AdvancementRevoke { .. } => Effects::default(),
+        _ => Effects {
+            barrier: true,
+            ..Effects::default()
+        },
+    }
+}
+
+fn scoreboard_effects(command: &ScoreboardCommand) -> Effects {
+    let mut effects = Effects::default();
+    match command {
+        ScoreboardCommand::PlayersSet {
+            target, objective, ..
+        } => effects.kills.extend(fake_player(target, objective)),
+        ScoreboardCommand::PlayersAdd {
+            target, objective, ..
+        }
+        | ScoreboardCommand::PlayersRemove {
+            target, objective, ..
+        } => {
+            effects.reads.extend(fake_player(target, objective));
+            effects.modifies.extend(fake_player(target, objective));
+        }
+        ScoreboardCommand::PlayersOperation {
+            target,
+            objective,
+            operation,
+            source,
+            source_objective,
+        } => {
+            let target = fake_player(target, objective);
+            let source = fake_player(source, source_objective);
+            effects.reads.extend(source.clone());
+            match operation {
+                // An entity's score could be missing, in which case the target is left unchanged
+                ScoreboardOperation::Assign if source.is_some() => effects.kills.extend(target),
+                ScoreboardOperation::Assign => effects.modifies.extend(target),
+                ScoreboardOperation::Swap => {
+                    effects.reads.extend(target.clone());
+                    effects.modifies.extend(target);
+                    effects.modifies.extend(source);
+                }
+                _ => {
+                    effects.reads.extend(target.clone());
+                    effects.modifies.extend(target);
+                }
+            }
+        }
+        ScoreboardCommand::PlayersGet { target, objective } => {
+            effects.reads.extend(fake_player(target, objective))
+        }
+        ScoreboardCommand::ObjectivesAdd { .. }
+        | ScoreboardCommand::ObjectivesRemove { .. }
+        | ScoreboardCommand::PlayersReset { .. }
+        | ScoreboardCommand::PlayersEnable { .. } => effects.barrier = true,
+    }
+    effects
+}
+
+/// Replace operations whose operand has a known value, and remove commands which do nothing
+fn propagate(commands: &mut Vec<Command>, temporaries: &HashSet<String>) -> bool {
+    let mut changed = false;
+    let mut values: HashMap<Score, Value> = HashMap::new();
+    let mut kept = Vec::new();
+
+    for mut command in commands.drain(..) {
+        changed |= substitute(&mut command, &values);
+        if does_nothing(&command) {
+            changed = true;
+            continue;
+        }
+
+        let effects = effects(&command);
+        for score in effects.kills.iter().chain(&effects.modifies) {
+            values.remove(score);
+            values.retain(|_, value| *value != Value::Copy(score.clone()));
+        }
+        if effects.barrier {
+            values.retain(|score, value| {
+                is_temporary(score, temporaries)
+                    && !matches!(value, Value::Copy(source) if !is_temporary(source, temporaries))
+            });
+        }
+
+        if let Command::Scoreboard { command } = &command {
+            match command {
+                ScoreboardCommand::PlayersSet {
+                    target,
+                    objective,
+                    score,
+                } => {
+                    if let Some(target) = fake_player(target, objective) {
+                        values.insert(target, Value::Constant(*score));
+                    }
+                }
+                ScoreboardCommand::PlayersOperation {
+                    target,
+                    objective,
+                    operation: ScoreboardOperation::Assign,
+                    source,
+                    source_objective,
+                } => {
+                    if let (Some(target), Some(source)) = (
+                        fake_player(target, objective),
+                        fake_player(source, source_objective),
+                    ) {
+                        values.insert(target, Value::Copy(source));
+                    }
+                }
+                _ => (),
+            }
+        }
+        kept.push(command);
+    }
+
+    *commands = kept;
+    changed
+}
+
+/// Get what is known about the value of a score
+fn known_value(values: &HashMap<Score, Value>, score: &Score) -> Option<Value> {
+    if score.objective == CONSTANT_OBJECTIVE {
+        if let Some(value) = score.name.strip_prefix('#').and_then(|v| v.parse().ok()) {
+            return Some(Value::Constant(value));
+        }
+    }
+    values.get(score).cloned()
+}
+
+/// Use the known value of an operation's operand in place of the operand. Return whether the command was changed
+fn substitute(command: &mut Command, values: &HashMap<Score, Value>) -> bool {
+    let scoreboard_command = match command {
+        Command::Scoreboard { command } => command,
+        Command::Execute { command, .. } => return substitute(command, values),
+        _ => return false,
+    };
+    let ScoreboardCommand::PlayersOperation {
+        target,
+        objective,
+        operation,
+        source,
+        source_objective,
+    } = scoreboard_command
+    else {
+        return false;
+    };
+    let value = match fake_player(source, source_objective) {
+        Some(source) => known_value(values, &source),
+        None => None,
+    };
+
+    let target = target.clone();
+    let objective = objective.clone();
+    let replacement = match (*operation, value) {
+        (ScoreboardOperation::Assign, Some(Value::Constant(score))) => {
+            ScoreboardCommand::PlayersSet {
+                target,
+                objective,
+                score,
+            }
+        }
+        (ScoreboardOperation::Addition, Some(Value::Constant(amount))) => {
+            match add(target, objective, amount) {
+                Some(replacement) => replacement,
+                None => return false,
+            }
+        }
+        (ScoreboardOperation::Subtraction, Some(Value::Constant(amount))) => {
+            match amount
+                .checked_neg()
+                .and_then(|amount| add(target, objective, amount))
+            {
+                Some(replacement) => replacement,
+                None => return false,
+            }
+        }
+        (operation, Some(Value::Copy(copied))) if operation != ScoreboardOperation::Swap => {
+            if fake_player(source, source_objective).as_ref() == Some(&copied) {
+                return false;
+            }
+            *source = CommandTarget::Name { name: copied.name };
+            *source_objective = copied.objective;
+            return true;
+        }
+        _ => return false,
+    };
+    *scoreboard_command = replacement;
+    true
+}
+
+/// A command adding `amount` to a score, since `add` and `remove` only take amounts of at least 0
+fn add(target: CommandTarget, objective: String, amount: i32) -> Option<ScoreboardCommand> {
+    if amount >= 0 {
+        Some(ScoreboardCommand::PlayersAdd {
+            target,
+            objective,
+            to_add: amount,
+        })
+    } else {
+        Some(ScoreboardCommand::PlayersRemove {
+            target,
+            objective,
+            to_remove: amount.checked_neg()?,
+        })
+    }
+}
+
+/// Whether a command never has any effect, such as assigning a score to itself
+fn does_nothing(command: &Command) -> bool {
+    match command {
+        Command::Scoreboard { command } => match command {
+            ScoreboardCommand::PlayersOperation {
+                target: CommandTarget::Name { name },
+                objective,
+                operation: ScoreboardOperation::Assign,
+                source: CommandTarget::Name { name: source },
+                source_objective,
+            } => name == source && objective == source_objective,
+            ScoreboardCommand::PlayersAdd { to_add: 0, .. }
+            | ScoreboardCommand::PlayersRemove { to_remove: 0, .. } => true,
+            _ => false,
+        },
+        Command::Execute { command, .. } => does_nothing(command),
+        _ => false,
+    }
+}
+
+/// Find a temporary which is only computed to be copied into a score, and compute the value in that score instead. Return whether a copy was removed
+fn coalesce(commands: &mut Vec<Command>, temporaries: &HashSet<String>) -> bool {
+    for copy in 0..commands.len() {
+        let (dest, temporary) = match &commands[copy] {
+            Command::Scoreboard {
+                command:
+                    ScoreboardCommand::PlayersOperation {
+                        target,
+                        objective,
+                        operation: ScoreboardOperation::Assign,
+                        source,
+                        source_objective,
+                    },
+            } => match (
+                fake_player(target, objective),
+                fake_player(source, source_objective),
+            ) {
+                (Some(dest), Some(source))
+                    if dest != source && is_temporary(&source, temporaries) =>
+                {
+                    (dest, source)
+                }
+                _ => continue,
+            },
+            _ => continue,
+        };
+        if is_live(&commands[copy + 1..], &temporary, temporaries) {
+            continue;
+        }
+
+        // The command which gave the temporary the value being copied
+        let definition = match (0..copy)
+            .rev()
+            .find(|&index| effects(&commands[index]).kills.contains(&temporary))
+        {
+            Some(definition) => definition,
+            None => continue,
+        };
+        if commands[definition + 1..copy]
+            .iter()
+            .any(|command| effects(command).touches(&dest, temporaries))
+        {
+            continue;
+        }
+
+        for command in &mut commands[definition..copy] {
+            rename(command, &temporary, &dest);
+        }
+        commands.remove(copy);
+        return true;
+    }
+    false
+}
+
+/// Whether the value `score` has before `commands` might be used by them or, if it isn't a temporary, after them
+fn is_live(commands: &[Command], score: &Score, temporaries: &HashSet<String>) -> bool {
+    for command in commands {
+        let effects = effects(command);
+        if effects.reads.contains(score)
+            || effects.modifies.contains(score)
+            || (effects.barrier && !is_temporary(score, temporaries))
+        {
+            return true;
+        }
+        if effects.kills.contains(score) {
+            return false;
+        }
+    }
+    !is_temporary(score, temporaries)
+}
+
+/// Replace every use of the score `from` in a command with `to`
+fn rename(command: &mut Command, from: &Score, to: &Score) {
+    let rename_target = |target: &mut CommandTarget, objective: &mut String| {
+        if fake_player(target, objective).as_ref() == Some(from) {
+            *target = CommandTarget::Name {
+                name: to.name.clone(),
+            };
+            *objective = to.objective.clone();
+        }
+    };
+
+    match command {
+        Command::Scoreboard { command } => match command {
+            ScoreboardCommand::PlayersSet {
+                target, objective, ..
+            }
+            | ScoreboardCommand::PlayersAdd {
+                target, objective, ..
+            }
+            | ScoreboardCommand::PlayersRemove {
+                target, objective, ..
+            }
+            | ScoreboardCommand::PlayersGet { target, objective } => {
+                rename_target(target, objective)
+            }
+            ScoreboardCommand::PlayersOperation {
+                target,
+                objective,
+                source,
+                source_objective,
+                ..
+            } => {
+                rename_target(target, objective);
+                rename_target(source, source_objective);
+            }
+            _ => (),
+        },
+        Command::Execute {
+            subcommands,
+            command,
+        } => {
+            for subcommand in subcommands {
+                if let ExecuteSubcommand::If { condition }
+                | ExecuteSubcommand::Unless { condition } = subcommand
+                {
+                    let ExecuteCondition::ScoreMatches {
+                        target, objective, ..
+                    } = condition;
+                    rename_target(target, objective);
+                }
+            }
+            rename(command, from, to);
+        }
+        _ => (),
+    }
+}
+
+/// Remove commands which only change a score that is set again before it's used, or a temporary that is never used again
+fn remove_dead_stores(commands: &mut Vec<Command>, temporaries: &HashSet<String>) -> bool {
+    let mut changed = false;
+    // Temporaries whose current values will be used
+    let mut live = HashSet::new();
+    // Other scores which will be set again before their current values can be used
+    let mut overwritten = HashSet::new();
+
+    let mut kept = Vec::new();
+    for command in commands.drain(..).rev() {
+        if let Some(written) = only_written_score(&command) {
+            let dead = if is_temporary(&written, temporaries) {
+                !live.contains(&written)
+            } else {
+                overwritten.contains(&written)
+            };
+            if dead {
+                changed = true;
+                continue;
+            }
+        }
+
+        let effects = effects(&command);
+        if effects.barrier {
+            overwritten.clear();
+        }
+        for score in effects.kills {
+            if is_temporary(&score, temporaries) {
+                live.remove(&score);
+            } else {
+                overwritten.insert(score);
+            }
+        }
+        for score in effects.modifies.into_iter().chain(effects.reads) {
+            if is_temporary(&score, temporaries) {
+                live.insert(score);
+            } else {
+                overwritten.remove(&score);
+            }
+        }
+        kept.push(command);
+    }
+
+    kept.reverse();
+    *commands = kept;
+    changed
+}
+
+/// Get the score changed by a command whose only effect is changing that score
+fn only_written_score(command: &Command) -> Option<Score> {
+    let Command::Scoreboard { command } = command else {
+        return None;
+    };
+    match command {
+        ScoreboardCommand::PlayersSet {
+            target, objective, ..
+        }
+        | ScoreboardCommand::PlayersAdd {
+            target, objective, ..
+        }
+        | ScoreboardCommand::PlayersRemove {
+            target, objective, ..
+        } => fake_player(target, objective),
+        ScoreboardCommand::PlayersOperation {
+            target,
+            objective,
+            operation,
+            ..
+        } if *operation != ScoreboardOperation::Swap => fake_player(target, objective),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcfunction::{MCFunctionID, ScoreRange, Selector, SelectorVariable};
+
+    const OBJECTIVE: &str = "mcfl_ints";
+
+    fn name(name: &str) -> CommandTarget {
+        CommandTarget::Name {
+            name: name.to_owned(),
+        }
+    }
+
+    fn executor() -> CommandTarget {
+        Selector::new(SelectorVariable::Executor).into()
+    }
+
+    fn set(target: CommandTarget, score: i32) -> Command {
+        Command::Scoreboard {
+            command: ScoreboardCommand::PlayersSet {
+                target,
+                objective: OBJECTIVE.to_owned(),
+                score,
+            },
+        }
+    }
+
+    fn operation(
+        target: CommandTarget,
+        operation: ScoreboardOperation,
+        source: CommandTarget,
+    ) -> Command {
+        Command::Scoreboard {
+            command: ScoreboardCommand::PlayersOperation {
+                target,
+                objective: OBJECTIVE.to_owned(),
+                operation,
+                source,
+                source_objective: OBJECTIVE.to_owned(),
+            },
+        }
+    }
+
+    fn call() -> Command {
+        Command::Function {
+            function: MCFunctionID::new("mcfl", &["f"]),
+        }
+    }
+
+    /// Optimize `commands`, treating `tmp_0` as the only temporary, and get the resulting lines
+    fn optimized(commands: Vec<Command>) -> Vec<String> {
+        let mut mcfunction = MCFunction::new();
+        for command in commands {
+            mcfunction.new_command(command);
+        }
+        optimize(&mut mcfunction, &HashSet::from(["tmp_0".to_owned()]));
+        mcfunction
+            .commands()
+            .iter()
+            .map(|command| command.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn coalesces_temporary_into_destination() {
+        // x = y - z
+        let lines = optimized(vec![
+            operation(name("tmp_0"), ScoreboardOperation::Assign, name("y")),
+            operation(name("tmp_0"), ScoreboardOperation::Subtraction, name("z")),
+            operation(name("x"), ScoreboardOperation::Assign, name("tmp_0")),
+        ]);
+        assert_eq!(
+            lines,
+            [
+                "scoreboard players operation x mcfl_ints = y mcfl_ints",
+                "scoreboard players operation x mcfl_ints -= z mcfl_ints",
+            ]
+        );
+    }
+
+    #[test]
+    fn does_not_coalesce_when_destination_is_read() {
+        // x = y - x, which would always be 0 if x were set to y first
+        let commands = || {
+            vec![
+                operation(name("tmp_0"), ScoreboardOperation::Assign, name("y")),
+                operation(name("tmp_0"), ScoreboardOperation::Subtraction, name("x")),
+                operation(name("x"), ScoreboardOperation::Assign, name("tmp_0")),
+            ]
+        };
+        let expected: Vec<String> = commands().iter().map(|c| c.to_string()).collect();
+        assert_eq!(optimized(commands()), expected);
+    }
+
+    #[test]
+    fn propagates_constants() {
+        let lines = optimized(vec![
+            set(name("x"), 5),
+            operation(name("y"), ScoreboardOperation::Assign, name("x")),
+        ]);
+        assert_eq!(
+            lines,
+            [
+                "scoreboard players set x mcfl_ints 5",
+                "scoreboard players set y mcfl_ints 5",
+            ]
+        );
+    }
+
+    #[test]
+    fn function_call_is_a_barrier() {
+        let lines = optimized(vec![
+            set(name("x"), 5),
+            call(),
+            operation(name("y"), ScoreboardOperation::Assign, name("x")),
+        ]);
+        assert_eq!(
+            lines,
+            [
+                "scoreboard players set x mcfl_ints 5",
+                "function mcfl:f",
+                "scoreboard players operation y mcfl_ints = x mcfl_ints",
+            ]
+        );
+
+        // The called function might change a
+        let lines = optimized(vec![
+            operation(name("tmp_0"), ScoreboardOperation::Assign, name("a")),
+            operation(name("tmp_0"), ScoreboardOperation::Addition, name("b")),
+            call(),
+            operation(name("a"), ScoreboardOperation::Assign, name("tmp_0")),
+        ]);
+        assert_eq!(
+            lines,
+            [
+                "scoreboard players operation tmp_0 mcfl_ints = a mcfl_ints",
+                "scoreboard players operation tmp_0 mcfl_ints += b mcfl_ints",
+                "function mcfl:f",
+                "scoreboard players operation a mcfl_ints = tmp_0 mcfl_ints",
+            ]
+        );
+    }
+
+    #[test]
+    fn macro_line_is_a_barrier() {
+        let lines = optimized(vec![
+            set(name("x"), 5),
+            Command::Macro {
+                template: "function mcfl:f with storage mcfl:args $(path)".to_owned(),
+            },
+            operation(name("y"), ScoreboardOperation::Assign, name("x")),
+        ]);
+        assert_eq!(
+            lines,
+            [
+                "scoreboard players set x mcfl_ints 5",
+                "$function mcfl:f with storage mcfl:args $(path)",
+                "scoreboard players operation y mcfl_ints = x mcfl_ints",
+            ]
+        );
+    }
+
+    #[test]
+    fn execute_may_not_run_its_command() {
+        let lines = optimized(vec![
+            set(name("tmp_0"), 1),
+            Command::Execute {
+                subcommands: vec![ExecuteSubcommand::If {
+                    condition: ExecuteCondition::ScoreMatches {
+                        target: name("c"),
+                        objective: OBJECTIVE.to_owned(),
+                        range: ScoreRange::exactly(1),
+                    },
+                }],
+                command: Box::new(set(name("tmp_0"), 2)),
+            },
+            operation(name("x"), ScoreboardOperation::Assign, name("tmp_0")),
+        ]);
+        assert_eq!(
+            lines,
+            [
+                "scoreboard players set x mcfl_ints 1",
+                "execute if score c mcfl_ints matches 1 run scoreboard players set x mcfl_ints 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn selector_source_may_be_missing_a_score() {
+        let lines = optimized(vec![
+            set(name("tmp_0"), 0),
+            operation(name("tmp_0"), ScoreboardOperation::Assign, executor()),
+            operation(name("x"), ScoreboardOperation::Assign, name("tmp_0")),
+        ]);
+        assert_eq!(
+            lines,
+            [
+                "scoreboard players set x mcfl_ints 0",
+                "scoreboard players operation x mcfl_ints = @s mcfl_ints",
+            ]
+        );
+    }
+
+    #[test]
+    fn selector_target_is_not_a_fake_player() {
+        let lines = optimized(vec![
+            set(name("tmp_0"), 3),
+            operation(executor(), ScoreboardOperation::Addition, name("tmp_0")),
+        ]);
+        assert_eq!(lines, ["scoreboard players add @s mcfl_ints 3"]);
+    }
+
+    #[test]
+    fn removes_overwritten_stores() {
+        let lines = optimized(vec![set(name("x"), 1), set(name("x"), 2)]);
+        assert_eq!(lines, ["scoreboard players set x mcfl_ints 2"]);
+
+        // Unlike a temporary, a variable's last value might be read by another function
+        let lines = optimized(vec![set(name("x"), 1), set(name("tmp_0"), 2)]);
+        assert_eq!(lines, ["scoreboard players set x mcfl_ints 1"]);
+    }
+
+    #[test]
+    fn keeps_stores_read_across_barriers() {
+        let lines = optimized(vec![set(name("x"), 1), call(), set(name("x"), 2)]);
+        assert_eq!(
+            lines,
+            [
+                "scoreboard players set x mcfl_ints 1",
+                "function mcfl:f",
+                "scoreboard players set x mcfl_ints 2",
+            ]
+        );
+    }
+}
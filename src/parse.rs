@@ -1,4 +1,7 @@
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 use pest::iterators::Pair;
@@ -8,8 +11,12 @@ use pest::pratt_parser::PrattParser;
 use pest::Parser;
 
 use crate::ast::ScopeModifier;
-use crate::ast::{ASTNode, ASTNodeType, VarType, VariableDeclaration};
+use crate::ast::{
+    ASTNode, ASTNodeType, AdvancementTrigger, Event, StringContext, VarType, VariableDeclaration,
+};
 use crate::error::CompileError;
+use crate::mcfunction::ScoreboardOperation;
+use crate::session::CompilerSession;
 use crate::tree::{NodeId, Tree};
 
 #[derive(Parser)]
@@ -21,24 +28,193 @@ lazy_static::lazy_static! {
         PrattParser::new()
             .op(Op::infix(Rule::add, Assoc::Left) | Op::infix(Rule::subtract, Assoc::Left))
             .op(Op::infix(Rule::multiply, Assoc::Left) | Op::infix(Rule::divide, Assoc::Left) | Op::infix(Rule::modulo, Assoc::Left))
+            .op(Op::prefix(Rule::negate) | Op::prefix(Rule::positive))
     };
 }
 
-/// Parse a string to an AST
-pub fn parse(toparse: &str) -> Result<Tree<ASTNode>, CompileError> {
-    let parsed = MCFLParser::parse(Rule::program, toparse)?.next().unwrap();
-    let mut tree = Tree::<ASTNode>::new();
+/// Parse a program's main file, and every file it imports, to an AST
+///
+/// Each file becomes a `Module` node under a single `Program` node. Imported files are looked for relative to the file importing them, then in each of `sources`
+pub fn parse(
+    session: &mut CompilerSession,
+    main: &Path,
+    sources: &[PathBuf],
+) -> Result<Tree<ASTNode>, CompileError> {
+    let mut tree = Tree::<ASTNode>::new(session.get_id());
 
-    parse_pair(&mut tree, parsed);
+    load_module(
+        &mut tree,
+        main,
+        sources,
+        &mut Vec::new(),
+        &mut HashMap::new(),
+    )?;
     return Ok(tree);
 
-    fn parse_pair(tree: &mut Tree<ASTNode>, pair: Pair<Rule>) -> Option<NodeId> {
+    /// Parse `file` and the files it imports, returning its `Module` node
+    ///
+    /// * `loading` - The files currently being loaded, each imported by the one before it
+    /// * `loaded` - The `Module` node of every file loaded so far, so that each file is only loaded once
+    fn load_module(
+        tree: &mut Tree<ASTNode>,
+        file: &Path,
+        sources: &[PathBuf],
+        loading: &mut Vec<PathBuf>,
+        loaded: &mut HashMap<PathBuf, NodeId>,
+    ) -> Result<NodeId, CompileError> {
+        let source = read_to_string(file).map_err(|err| CompileError::ReadSourceError {
+            file: file.display().to_string(),
+            err,
+        })?;
+        let program = MCFLParser::parse(Rule::program, &source)
+            .map_err(|err| err.with_path(&file.display().to_string()))?
+            .next()
+            .unwrap();
+        check_numbers(program.clone(), file)?;
+
+        let root = match tree.get_root() {
+            Ok(root) => root,
+            Err(_) => tree.new_node(ASTNode::new(ASTNodeType::Program, program.clone(), file)),
+        };
+        let name = file
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let module = tree.new_node(ASTNode::new(
+            ASTNodeType::Module { name },
+            program.clone(),
+            file,
+        ));
+        tree.append_to(root, module)?;
+
+        let canonical = file.canonicalize()?;
+        loaded.insert(canonical.clone(), module);
+        loading.push(canonical);
+
+        for pair in program.into_inner() {
+            if let Some(item) = parse_pair(tree, pair, file) {
+                tree.append_to(module, item)?;
+            }
+        }
+        check_negated_literals(tree, module)?;
+
+        for item in tree.get_children(module)?.clone() {
+            let node = tree.get_node(item)?;
+            let path = match &node.node_type {
+                ASTNodeType::Import { path, .. } => path.clone(),
+                _ => continue,
+            };
+            let context = node.context.clone();
+
+            let import_file = match find_import(file, &path, sources) {
+                Some(import_file) => import_file,
+                None => return Err(CompileError::ImportNotFound { path, context }),
+            };
+            let canonical = import_file.canonicalize()?;
+            if let Some(start) = loading.iter().position(|f| *f == canonical) {
+                let chain = loading[start..]
+                    .iter()
+                    .chain([&canonical])
+                    .map(|f| f.display().to_string())
+                    .collect();
+                return Err(CompileError::ImportCycle { chain, context });
+            }
+
+            let imported = match loaded.get(&canonical) {
+                Some(imported) => *imported,
+                None => load_module(tree, &import_file, sources, loading, loaded)?,
+            };
+            if let ASTNodeType::Import { module, .. } = &mut tree.get_node_mut(item)?.node_type {
+                *module = Some(imported);
+            }
+        }
+
+        loading.pop();
+        Ok(module)
+    }
+
+    /// Check that every number, duration and migration version in a file fits in a score, so that parsing them can't fail
+    ///
+    /// The literal `2147483648` is let through, since `-2147483648` is parsed as its negation. `check_negated_literals` checks how it's used once the file's AST is built
+    fn check_numbers(program: Pair<Rule>, file: &Path) -> Result<(), CompileError> {
+        for pair in program.into_inner().flatten() {
+            let fits = match pair.as_rule() {
+                Rule::number_literal => match pair.as_str().parse::<i64>() {
+                    Ok(value) => i32::try_from(value).is_ok() || value == 1 << 31,
+                    Err(_) => false,
+                },
+                Rule::migration_version => pair.as_str().parse::<i32>().is_ok(),
+                Rule::duration if parse_duration(pair.clone()).is_none() => {
+                    return Err(CompileError::DurationTooLong {
+                        context: StringContext::new(pair, file),
+                    });
+                }
+                _ => true,
+            };
+            if !fits {
+                return Err(CompileError::NumberOutOfRange {
+                    context: StringContext::new(pair, file),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that every `2147483648` literal under `module` is negated exactly once, which is the only way its value fits in a score
+    fn check_negated_literals(tree: &Tree<ASTNode>, module: NodeId) -> Result<(), CompileError> {
+        let is_negate = |node: NodeId| {
+            matches!(
+                tree.get_node(node).map(|node| &node.node_type),
+                Ok(ASTNodeType::Negate)
+            )
+        };
+        for node in tree.iter_subtree(module)? {
+            let literal = tree.get_node(node)?;
+            // No other literal is parsed to this value
+            if let ASTNodeType::NumberLiteral { value: i32::MIN } = literal.node_type {
+                let parent = tree.get_parent(node)?;
+                if !is_negate(parent) || tree.get_parent(parent).is_ok_and(is_negate) {
+                    return Err(CompileError::NumberOutOfRange {
+                        context: literal.context.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the file imported as `path` by `importer`
+    fn find_import(importer: &Path, path: &str, sources: &[PathBuf]) -> Option<PathBuf> {
+        let importer_dir = importer.parent().unwrap_or(Path::new("")).to_owned();
+        [importer_dir]
+            .iter()
+            .chain(sources)
+            .map(|dir| dir.join(path))
+            .find(|import_file| import_file.is_file())
+    }
+
+    fn parse_pair(tree: &mut Tree<ASTNode>, pair: Pair<Rule>, file: &Path) -> Option<NodeId> {
         let mut inner_pairs = pair.clone().into_inner();
         let rule = pair.as_rule();
 
         let node_type: Option<ASTNodeType> = match rule {
-            Rule::program => Some(ASTNodeType::Program),
+            Rule::import_declaration => Some(ASTNodeType::Import {
+                path: parse_string_literal(inner_pairs.next().unwrap()),
+                module: None,
+            }),
+            Rule::mod_declaration => Some(ASTNodeType::Import {
+                path: format!("{}.mcfl", inner_pairs.next().unwrap().as_str()),
+                module: None,
+            }),
             Rule::function => Some(ASTNodeType::Function {
+                public: {
+                    if let Rule::pub_keyword = inner_pairs.peek().unwrap().as_rule() {
+                        inner_pairs.next();
+                        true
+                    } else {
+                        false
+                    }
+                },
                 name: inner_pairs.next().unwrap().as_str().to_owned(),
                 params: parse_param_list(inner_pairs.next().unwrap()),
                 return_type: {
@@ -51,24 +227,63 @@ pub fn parse(toparse: &str) -> Result<Tree<ASTNode>, CompileError> {
             }),
             Rule::mcfunction => Some(ASTNodeType::MCFunction {
                 name: inner_pairs.next().unwrap().as_str().to_owned(),
+                advancement: {
+                    if let Rule::advancement_trigger = inner_pairs.peek().unwrap().as_rule() {
+                        Some(parse_advancement_trigger(inner_pairs.next().unwrap()))
+                    } else {
+                        None
+                    }
+                },
+            }),
+            Rule::trigger_declaration => Some(ASTNodeType::TriggerDeclaration {
+                name: inner_pairs.next().unwrap().as_str().to_owned(),
+            }),
+            Rule::event_handler => Some(ASTNodeType::EventHandler {
+                event: parse_event(inner_pairs.next().unwrap()),
+            }),
+            Rule::migration => Some(ASTNodeType::Migration {
+                version: inner_pairs.next().unwrap().as_str().parse().unwrap(),
+            }),
+            Rule::schedule => Some(ASTNodeType::Schedule {
+                ticks: parse_duration(inner_pairs.next().unwrap()).unwrap(),
+            }),
+            Rule::sleep => Some(ASTNodeType::Sleep {
+                ticks: parse_duration(inner_pairs.next().unwrap()).unwrap(),
             }),
             Rule::block => Some(ASTNodeType::Block),
             Rule::EOI => None,
             Rule::variable_declaration => {
-                inner_pairs.next();
-                inner_pairs.next();
+                inner_pairs.by_ref().for_each(drop);
                 Some(ASTNodeType::VariableDeclaration {
                     declaration: parse_variable_declaration(pair.clone()),
                 })
             }
-            Rule::assignment => Some(ASTNodeType::Assignment),
+            Rule::const_declaration => Some(ASTNodeType::ConstDeclaration {
+                // Constants can only be ints
+                name: inner_pairs.nth(1).unwrap().as_str().to_owned(),
+            }),
+            Rule::assignment => Some(match inner_pairs.clone().nth(1) {
+                Some(operator)
+                    if matches!(
+                        operator.as_rule(),
+                        Rule::assignment_operator | Rule::step_operator
+                    ) =>
+                {
+                    ASTNodeType::CompoundAssignment {
+                        operation: parse_assignment_operator(operator),
+                    }
+                }
+                _ => ASTNodeType::Assignment,
+            }),
+            // `++` and `--` change a variable by 1
+            Rule::step_operator => Some(ASTNodeType::NumberLiteral { value: 1 }),
             Rule::name => Some(ASTNodeType::Identifier {
                 id: pair.as_str().to_owned(),
             }),
             Rule::number_literal => Some(ASTNodeType::NumberLiteral {
                 value: parse_number_literal(pair.clone()),
             }),
-            Rule::binary_operation => None,
+            Rule::binary_operation | Rule::global_declaration | Rule::assignment_operator => None,
             Rule::return_statement => Some(ASTNodeType::ReturnStatement),
             Rule::function_call => Some(ASTNodeType::FunctionCall {
                 id: inner_pairs.next().unwrap().as_str().to_owned(),
@@ -76,10 +291,22 @@ pub fn parse(toparse: &str) -> Result<Tree<ASTNode>, CompileError> {
             _ => unreachable!("{:?}", pair.as_rule()),
         };
 
-        if let Some(n_type) = node_type {
-            let node = tree.new_node(ASTNode::new(n_type, pair));
+        if let Rule::global_declaration = rule {
+            // The declaration is wrapped in a block so that it can be analyzed and compiled like the body of a function
+            let node = tree.new_node(ASTNode::new(
+                ASTNodeType::GlobalDeclaration,
+                pair.clone(),
+                file,
+            ));
+            let block = tree.new_node(ASTNode::new(ASTNodeType::Block, pair, file));
+            let statement = parse_pair(tree, inner_pairs.next().unwrap(), file).unwrap();
+            tree.append_to(block, statement).unwrap();
+            tree.append_to(node, block).unwrap();
+            Some(node)
+        } else if let Some(n_type) = node_type {
+            let node = tree.new_node(ASTNode::new(n_type, pair, file));
             for child in inner_pairs {
-                if let Some(c_node) = parse_pair(tree, child) {
+                if let Some(c_node) = parse_pair(tree, child, file) {
                     tree.append_to(node, c_node).unwrap();
                 }
             }
@@ -89,7 +316,23 @@ pub fn parse(toparse: &str) -> Result<Tree<ASTNode>, CompileError> {
 
             // This will break if `use std::borrow::BorrowMut` is present ;(
             let ret_node = PRATT_PARSER
-                .map_primary(|primary| parse_pair(*treerc.borrow_mut(), primary))
+                .map_primary(|primary| parse_pair(*treerc.borrow_mut(), primary, file))
+                .map_prefix(|op, operand| match op.as_rule() {
+                    Rule::negate => {
+                        let node = treerc.borrow_mut().new_node(ASTNode::new(
+                            ASTNodeType::Negate,
+                            op,
+                            file,
+                        ));
+                        treerc
+                            .borrow_mut()
+                            .append_to(node, operand.unwrap())
+                            .unwrap();
+                        Some(node)
+                    }
+                    Rule::positive => operand,
+                    _ => unreachable!(),
+                })
                 .map_infix(|lhs, op, rhs| {
                     let node_type = match op.as_rule() {
                         Rule::add => ASTNodeType::Add,
@@ -100,7 +343,9 @@ pub fn parse(toparse: &str) -> Result<Tree<ASTNode>, CompileError> {
                         _ => unreachable!(),
                     };
 
-                    let node = treerc.borrow_mut().new_node(ASTNode::new(node_type, op));
+                    let node = treerc
+                        .borrow_mut()
+                        .new_node(ASTNode::new(node_type, op, file));
                     treerc.borrow_mut().append_to(node, lhs.unwrap()).unwrap();
                     treerc.borrow_mut().append_to(node, rhs.unwrap()).unwrap();
                     Some(node)
@@ -119,6 +364,27 @@ pub fn parse(toparse: &str) -> Result<Tree<ASTNode>, CompileError> {
         }
     }
 
+    fn parse_advancement_trigger(pair: Pair<Rule>) -> AdvancementTrigger {
+        let mut inner_pairs = pair.into_inner();
+        AdvancementTrigger {
+            trigger: parse_string_literal(inner_pairs.next().unwrap()),
+            conditions: inner_pairs.next().map(parse_string_literal),
+        }
+    }
+
+    fn parse_event(pair: Pair<Rule>) -> Event {
+        match pair.as_rule() {
+            Rule::trigger_event => Event::Trigger {
+                trigger: pair.into_inner().next().unwrap().as_str().to_owned(),
+            },
+            Rule::death_event => Event::Death,
+            Rule::join_event => Event::Join,
+            Rule::respawn_event => Event::Respawn,
+            Rule::kill_event => Event::Kill,
+            _ => unreachable!(),
+        }
+    }
+
     fn parse_var_scope(pair_option: Option<Pair<Rule>>) -> ScopeModifier {
         if let Some(pair) = pair_option {
             match pair.into_inner().next().unwrap().as_rule() {
@@ -148,7 +414,147 @@ pub fn parse(toparse: &str) -> Result<Tree<ASTNode>, CompileError> {
         }
     }
 
+    /// Parse a literal checked by `check_numbers`. The only literal too large for a score it allows is the `2147483648` in `-2147483648`, which wraps around to the same value as its negation, and is checked by `check_negated_literals`
     fn parse_number_literal(pair: Pair<Rule>) -> i32 {
-        pair.as_str().parse().unwrap()
+        pair.as_str().parse().unwrap_or(i32::MIN)
+    }
+
+    /// Get the operation a compound assignment or step applies to its variable
+    fn parse_assignment_operator(pair: Pair<Rule>) -> ScoreboardOperation {
+        match pair.as_str() {
+            "+=" | "++" => ScoreboardOperation::Addition,
+            "-=" | "--" => ScoreboardOperation::Subtraction,
+            "*=" => ScoreboardOperation::Multiplication,
+            "/=" => ScoreboardOperation::Division,
+            "%=" => ScoreboardOperation::Modulo,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Parse a duration to a number of ticks, or `None` if there are too many ticks to count in a score
+    fn parse_duration(pair: Pair<Rule>) -> Option<i32> {
+        let mut inner_pairs = pair.into_inner();
+        let amount: i32 = inner_pairs.next().unwrap().as_str().parse().ok()?;
+        let ticks_per_unit = match inner_pairs.next().map(|unit| unit.as_str()) {
+            None | Some("t") => 1,
+            Some("s") => 20,
+            Some("d") => 24000,
+            _ => unreachable!(),
+        };
+        amount.checked_mul(ticks_per_unit)
+    }
+
+    fn parse_string_literal(pair: Pair<Rule>) -> String {
+        let mut string = String::new();
+        let mut chars = pair.into_inner().next().unwrap().as_str().chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next().unwrap() {
+                    'n' => string.push('\n'),
+                    't' => string.push('\t'),
+                    escaped => string.push(escaped),
+                }
+            } else {
+                string.push(c);
+            }
+        }
+        string
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{remove_file, write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::compile::CompileOptions;
+
+    /// Parse `expression` as a variable's initial value, and write it back with every operation in parentheses
+    fn parse_expression(expression: &str) -> Result<String, CompileError> {
+        static FILES: AtomicUsize = AtomicUsize::new(0);
+        let file = std::env::temp_dir().join(format!(
+            "mcfl_parse_{}_{}.mcfl",
+            std::process::id(),
+            FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        write(
+            &file,
+            format!(
+                "mcfunction tick {{ int a; int x; int y = {}; }}",
+                expression
+            ),
+        )?;
+        let mut session = CompilerSession::new(CompileOptions::default());
+        let parsed = parse(&mut session, &file, &[]);
+        remove_file(&file)?;
+
+        let tree = parsed?;
+        let assignment = tree
+            .iter_subtree(tree.get_root()?)?
+            .find(|&node| {
+                matches!(
+                    tree.get_node(node).unwrap().node_type,
+                    ASTNodeType::Assignment
+                )
+            })
+            .unwrap();
+        Ok(render(&tree, tree.get_last_child(assignment)?))
+    }
+
+    fn render(tree: &Tree<ASTNode>, node: NodeId) -> String {
+        let operand = |index: usize| render(tree, tree.get_children(node).unwrap()[index]);
+        match &tree.get_node(node).unwrap().node_type {
+            ASTNodeType::Identifier { id } => id.clone(),
+            ASTNodeType::NumberLiteral { value } => value.to_string(),
+            ASTNodeType::Negate => format!("(-{})", operand(0)),
+            ASTNodeType::Add => format!("({} + {})", operand(0), operand(1)),
+            ASTNodeType::Subtract => format!("({} - {})", operand(0), operand(1)),
+            ASTNodeType::Multiply => format!("({} * {})", operand(0), operand(1)),
+            node_type => panic!("unexpected {:?}", node_type),
+        }
+    }
+
+    #[test]
+    fn subtraction() {
+        assert_eq!(parse_expression("a - 5").unwrap(), "(a - 5)");
+        assert_eq!(parse_expression("a -5").unwrap(), "(a - 5)");
+        assert_eq!(parse_expression("a-5").unwrap(), "(a - 5)");
+        assert_eq!(parse_expression("a - 5 - x").unwrap(), "((a - 5) - x)");
+    }
+
+    #[test]
+    fn prefix_operators() {
+        assert_eq!(parse_expression("-x").unwrap(), "(-x)");
+        assert_eq!(parse_expression("+x").unwrap(), "x");
+        assert_eq!(parse_expression("--x").unwrap(), "(-(-x))");
+        assert_eq!(parse_expression("a - -5").unwrap(), "(a - (-5))");
+        assert_eq!(parse_expression("-a * x").unwrap(), "((-a) * x)");
+    }
+
+    #[test]
+    fn score_range_boundary() {
+        assert_eq!(parse_expression("2147483647").unwrap(), "2147483647");
+        assert_eq!(parse_expression("-2147483647").unwrap(), "(-2147483647)");
+        assert!(parse_expression("-2147483648").is_ok());
+        assert!(parse_expression("a - -2147483648").is_ok());
+
+        for out_of_range in [
+            "2147483648",
+            "-2147483649",
+            "a - 2147483648",
+            "--2147483648",
+            "- -2147483648",
+            "-(-2147483648)",
+        ] {
+            assert!(
+                matches!(
+                    parse_expression(out_of_range),
+                    Err(CompileError::NumberOutOfRange { .. })
+                ),
+                "{}",
+                out_of_range
+            );
+        }
     }
 }
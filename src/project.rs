@@ -0,0 +1,166 @@
+use std::fs::read_to_string;
+use std::path::{Component, Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::{compile::CompileOptions, error::CompileError, mcfunction::is_valid_id_part};
+
+/// Name of the project manifest looked for in the current directory
+pub const MANIFEST_FILE: &str = "mcfl.toml";
+
+/// Everything needed to build a datapack, usually read from an `mcfl.toml` manifest
+pub struct Project {
+    pub options: CompileOptions,
+
+    /// Directories searched, in order, for source files
+    pub sources: Vec<PathBuf>,
+
+    /// The source file to compile, relative to one of `sources`
+    pub main: PathBuf,
+
+    /// The directory the datapack is saved in
+    pub output: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    pack: PackSection,
+    #[serde(default)]
+    build: BuildSection,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PackSection {
+    name: String,
+    description: Option<String>,
+    namespace: Option<String>,
+    private_namespace: Option<String>,
+    target: Option<String>,
+    #[serde(default)]
+    disable_on_uninstall: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct BuildSection {
+    sources: Vec<PathBuf>,
+    main: PathBuf,
+    output: PathBuf,
+    zip: bool,
+    optimize: bool,
+}
+
+impl Default for BuildSection {
+    fn default() -> Self {
+        BuildSection {
+            sources: vec![PathBuf::from("src")],
+            main: PathBuf::from("main.mcfl"),
+            output: PathBuf::from("out"),
+            zip: false,
+            optimize: true,
+        }
+    }
+}
+
+impl Project {
+    /// Read the manifest at `path`. Relative paths in it are relative to the directory it's in
+    pub fn load(path: &Path) -> Result<Project, CompileError> {
+        let invalid = |reason: String| CompileError::InvalidProjectManifest {
+            path: path.display().to_string(),
+            reason,
+        };
+
+        let manifest: Manifest =
+            toml::from_str(&read_to_string(path)?).map_err(|err| invalid(err.to_string()))?;
+        let pack = manifest.pack;
+        let root = path.parent().unwrap_or(Path::new(""));
+
+        if !is_valid_pack_name(&pack.name) {
+            return Err(invalid(format!(
+                "pack name {:?} can't be used as a file name. It must not be empty, '.' or '..', or contain '/' or '\\'",
+                pack.name
+            )));
+        }
+
+        let mut options = CompileOptions::default();
+        if let Some(target) = pack.target {
+            options.target = target.parse()?;
+        }
+        if let Some(description) = pack.description {
+            options.description = description;
+        }
+        options.namespace = pack.namespace.unwrap_or_else(|| pack.name.clone());
+        options.private_namespace = pack
+            .private_namespace
+            .unwrap_or_else(|| format!("{}_private", options.namespace));
+        options.name = pack.name;
+        options.disable_on_uninstall = pack.disable_on_uninstall;
+        options.archive = manifest.build.zip;
+        options.optimize = manifest.build.optimize;
+
+        for namespace in [&options.namespace, &options.private_namespace] {
+            if !is_valid_id_part(namespace) {
+                return Err(invalid(format!(
+                    "namespace {:?} may only contain lowercase letters, digits, '_', '-' and '.'",
+                    namespace
+                )));
+            }
+        }
+        if options.namespace == options.private_namespace {
+            return Err(invalid(
+                "the public and private namespaces must be different".to_owned(),
+            ));
+        }
+
+        Ok(Project {
+            options,
+            sources: manifest
+                .build
+                .sources
+                .iter()
+                .map(|source| root.join(source))
+                .collect(),
+            main: manifest.build.main,
+            output: root.join(manifest.build.output),
+        })
+    }
+
+    /// Find the main source file in the first source directory containing it
+    pub fn main_file(&self) -> Result<PathBuf, CompileError> {
+        self.sources
+            .iter()
+            .map(|source| source.join(&self.main))
+            .find(|path| path.is_file())
+            .ok_or_else(|| CompileError::SourceNotFound {
+                file: self.main.display().to_string(),
+                sources: self
+                    .sources
+                    .iter()
+                    .map(|source| source.display().to_string())
+                    .collect(),
+            })
+    }
+}
+
+/// Whether a pack name can name the folder or archive the pack is saved as, without the path leaving the output directory
+fn is_valid_pack_name(name: &str) -> bool {
+    !name.contains(['/', '\\'])
+        && matches!(
+            Path::new(name).components().collect::<Vec<_>>()[..],
+            [Component::Normal(_)]
+        )
+}
+
+impl Default for Project {
+    /// The project used without a manifest, which compiles `examples/test.mcfl` to `out`
+    fn default() -> Self {
+        Project {
+            options: CompileOptions::default(),
+            sources: vec![PathBuf::new()],
+            main: PathBuf::from("examples/test.mcfl"),
+            output: PathBuf::from("out"),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::{compile::CompileOptions, id_tracker::IDTracker, names::NameAllocator};
+
+/// State belonging to a single compilation, so that separate compilations never share IDs or names
+pub struct CompilerSession {
+    pub options: CompileOptions,
+
+    /// Names of the scores holding variables and other compiler state
+    pub names: NameAllocator,
+
+    ids: IDTracker,
+}
+
+impl CompilerSession {
+    pub fn new(options: CompileOptions) -> CompilerSession {
+        CompilerSession {
+            names: NameAllocator::new(options.target.max_fake_player_len()),
+            options,
+            ids: IDTracker::new(),
+        }
+    }
+
+    /// Get an ID which is unique within this compilation
+    pub fn get_id(&mut self) -> usize {
+        self.ids.get_id()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+    use std::thread;
+
+    use super::*;
+    use crate::compile_file;
+
+    /// Compile the example program in a new session, and get the datapack's files
+    fn compile_example() -> BTreeMap<String, Vec<u8>> {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let mut session = CompilerSession::new(CompileOptions::default());
+        compile_file(
+            &mut session,
+            &root.join("examples/test.mcfl"),
+            &[PathBuf::from(root)],
+        )
+        .unwrap()
+        .files()
+    }
+
+    #[test]
+    fn concurrent_sessions_are_independent() {
+        let threads: Vec<_> = (0..2)
+            .map(|_| thread::spawn(|| (0..10).map(|_| compile_example()).collect::<Vec<_>>()))
+            .collect();
+        let expected = compile_example();
+        for thread in threads {
+            for files in thread.join().unwrap() {
+                assert_eq!(files, expected);
+            }
+        }
+    }
+}
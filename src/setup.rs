@@ -0,0 +1,159 @@
+use std::collections::BTreeSet;
+
+use crate::mcfunction::{
+    Command, CommandTarget, ExecuteCondition, ExecuteSubcommand, MCFunction, MCFunctionID,
+    ObjectiveCriteria, ScoreRange, ScoreboardCommand,
+};
+
+/// Objective holding a score for every constant used as the source of a scoreboard operation, such as `#3` for `x * 3`
+pub const CONSTANT_OBJECTIVE: &str = "mcfl_consts";
+
+/// Fake player holding the version of the program that last saved the datapack's state
+pub const STATE_VERSION_PLAYER: &str = "#state_version";
+
+/// Everything the program needs to exist before any of its functions run, gathered while compiling so that it's set up exactly once by the generated load function
+pub struct Setup {
+    /// Objective the state version is stored on
+    state_objective: String,
+
+    /// Objectives in the order they were first needed
+    objectives: Vec<(String, ObjectiveCriteria)>,
+
+    /// Values of the constant scores, which are each set once
+    constants: BTreeSet<i32>,
+
+    /// Commands giving global variables declared outside of functions their first values
+    initializers: MCFunction,
+
+    /// Functions run to upgrade state saved by older versions of the program, by the version they upgrade to
+    migrations: Vec<(i32, MCFunctionID)>,
+}
+
+impl Setup {
+    /// * `state_objective` - Objective to store the state version on, which must also be added with `add_objective`
+    pub fn new(state_objective: &str) -> Setup {
+        Setup {
+            state_objective: state_objective.to_owned(),
+            objectives: Vec::new(),
+            constants: BTreeSet::new(),
+            initializers: MCFunction::new(),
+            migrations: Vec::new(),
+        }
+    }
+
+    /// Record that the program uses an objective. Each objective is only added once, with the criteria it was first recorded with
+    pub fn add_objective(&mut self, id: &str, criteria: ObjectiveCriteria) {
+        if !self.objectives.iter().any(|(existing, _)| existing == id) {
+            self.objectives.push((id.to_owned(), criteria));
+        }
+    }
+
+    /// IDs of every objective the program uses
+    pub fn objectives(&self) -> impl Iterator<Item = &str> {
+        self.objectives.iter().map(|(id, _)| id.as_str())
+    }
+
+    /// Get a score that always holds `value`, for operations which can only take their operand from a score
+    pub fn add_constant(&mut self, value: i32) -> (CommandTarget, String) {
+        self.add_objective(CONSTANT_OBJECTIVE, ObjectiveCriteria::Dummy);
+        self.constants.insert(value);
+        (constant_score(value), CONSTANT_OBJECTIVE.to_owned())
+    }
+
+    /// Run the initializer of a global variable, after every objective has been added
+    pub fn add_initializer(&mut self, initializer: MCFunction) {
+        self.initializers.append(initializer);
+    }
+
+    /// Run `function` once if the stored state is older than `version`
+    pub fn add_migration(&mut self, version: i32, function: MCFunctionID) {
+        self.migrations.push((version, function));
+    }
+
+    /// The version of the program's state, which is that of its newest migration, or 0 without any
+    pub fn state_version(&self) -> i32 {
+        self.migrations
+            .iter()
+            .map(|(version, _)| *version)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Generate the load function, which runs before the program's `startup` functions
+    pub fn load_function(mut self) -> MCFunction {
+        let mut load = MCFunction::new();
+        let state_version = self.state_version();
+        for (id, criteria) in self.objectives {
+            load.new_command(
+                ScoreboardCommand::ObjectivesAdd {
+                    id,
+                    criteria,
+                    name: None,
+                }
+                .into(),
+            );
+        }
+
+        for value in self.constants {
+            load.new_command(
+                ScoreboardCommand::PlayersSet {
+                    target: constant_score(value),
+                    objective: CONSTANT_OBJECTIVE.to_owned(),
+                    score: value,
+                }
+                .into(),
+            );
+        }
+        load.append(self.initializers);
+
+        let version_player = CommandTarget::Name {
+            name: STATE_VERSION_PLAYER.to_owned(),
+        };
+        let stored_version = |range| ExecuteSubcommand::If {
+            condition: ExecuteCondition::ScoreMatches {
+                target: version_player.clone(),
+                objective: self.state_objective.clone(),
+                range,
+            },
+        };
+        let set_version = || -> Command {
+            ScoreboardCommand::PlayersSet {
+                target: version_player.clone(),
+                objective: self.state_objective.clone(),
+                score: state_version,
+            }
+            .into()
+        };
+
+        if !self.migrations.is_empty() {
+            // A new installation has no state to migrate
+            load.new_command(Command::Execute {
+                subcommands: vec![ExecuteSubcommand::Unless {
+                    condition: ExecuteCondition::ScoreMatches {
+                        target: version_player.clone(),
+                        objective: self.state_objective.clone(),
+                        range: ScoreRange::any(),
+                    },
+                }],
+                command: Box::new(set_version()),
+            });
+
+            self.migrations.sort_by_key(|(version, _)| *version);
+            for (version, function) in self.migrations {
+                load.new_command(Command::Execute {
+                    subcommands: vec![stored_version(ScoreRange::at_most(version - 1))],
+                    command: Box::new(Command::Function { function }),
+                });
+            }
+        }
+        load.new_command(set_version());
+        load
+    }
+}
+
+/// The fake player holding the constant `value`
+fn constant_score(value: i32) -> CommandTarget {
+    CommandTarget::Name {
+        name: format!("#{}", value),
+    }
+}
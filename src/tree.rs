@@ -1,6 +1,6 @@
 use std::{collections::HashMap, fmt::Debug};
 
-use crate::{error::TreeError, id_tracker::ID_TRACKER};
+use crate::error::TreeError;
 
 /// Generic tree struct that stores a vector of nodes which can be accessed with their ID's.
 pub struct Tree<T> {
@@ -10,20 +10,16 @@ pub struct Tree<T> {
     id: usize,
 }
 
-impl<T> Default for Tree<T> {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 impl<T> Tree<T> {
     /// Create a new empty tree with no root
-    pub fn new() -> Tree<T> {
+    ///
+    /// * `id` - An ID distinguishing this tree's nodes from those of other trees
+    pub fn new(id: usize) -> Tree<T> {
         Tree {
             nodes: HashMap::new(),
             next_index: 0,
             root: None,
-            id: ID_TRACKER.lock().unwrap().get_id(),
+            id,
         }
     }
 
@@ -97,10 +93,12 @@ impl<T> Tree<T> {
         c_node.parent = Some(parent);
         c_node.previous_sibling = sibling_id;
 
-        let depth_shift = parent_depth - c_node.depth + 1;
+        // A detached node can be moved up the tree as well as down
+        let depth_shift = i64::from(parent_depth) + 1 - i64::from(c_node.depth);
         let subtree: Vec<NodeId> = self.iter_subtree(child)?.collect();
         for c in subtree {
-            self.get_treenode_mut(c)?.depth += depth_shift;
+            let c_node = self.get_treenode_mut(c)?;
+            c_node.depth = (i64::from(c_node.depth) + depth_shift) as u32;
         }
 
         if let Some(sibling) = sibling_id {
@@ -110,13 +108,44 @@ impl<T> Tree<T> {
         Ok(())
     }
 
+    /// Take `child` away from its parent, keeping its descendants, so that it can be appended somewhere else
+    pub fn detach(&mut self, child: NodeId) -> Result<(), TreeError> {
+        let c_node = self.get_treenode_mut(child)?;
+        let parent = c_node.parent.take();
+        let previous_sibling = c_node.previous_sibling.take();
+        let next_sibling = c_node.next_sibling.take();
+
+        if let Some(parent) = parent {
+            self.get_treenode_mut(parent)?
+                .children
+                .retain(|&c| c != child);
+        }
+        if let Some(previous) = previous_sibling {
+            self.get_treenode_mut(previous)?.next_sibling = next_sibling;
+        }
+        if let Some(next) = next_sibling {
+            self.get_treenode_mut(next)?.previous_sibling = previous_sibling;
+        }
+        Ok(())
+    }
+
+    /// Remove every descendant of `parent` from the tree
+    pub fn remove_children(&mut self, parent: NodeId) -> Result<(), TreeError> {
+        let descendants: Vec<NodeId> = self.iter_subtree(parent)?.skip(1).collect();
+        for descendant in descendants {
+            self.nodes.remove(&descendant);
+        }
+        self.get_treenode_mut(parent)?.children.clear();
+        Ok(())
+    }
+
     /// Returns an iterator over the subtree starting with `head`. Implemented non-recursively
-    pub fn iter_subtree(&self, head: NodeId) -> Result<TreeIterator<T>, TreeError> {
+    pub fn iter_subtree(&self, head: NodeId) -> Result<TreeIterator<'_, T>, TreeError> {
         TreeIterator::iter_subtree(self, head)
     }
 
     /// Returns an iterator starting from a node and moving up the tree until the root
-    pub fn iter_ascend(&self, start: NodeId) -> Result<TreeAscender<T>, TreeError> {
+    pub fn iter_ascend(&self, start: NodeId) -> Result<TreeAscender<'_, T>, TreeError> {
         TreeAscender::ascend_tree(self, start)
     }
 
@@ -334,6 +363,11 @@ impl<'a, T> Iterator for TreeIterator<'a, T> {
             self.cur_node = *node.children.first().unwrap();
             return Some(ret);
         }
+        // A subtree of one node ends without moving on to its siblings
+        if ret == self.head {
+            self.finished = true;
+            return Some(ret);
+        }
 
         loop {
             if let Some(sib) = node.next_sibling {
@@ -0,0 +1,84 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    datapack::DataPack,
+    mcfunction::{
+        Command, CommandTarget, MCFunction, ScoreboardCommand, Selector, SelectorVariable,
+        TagAction,
+    },
+    setup::Setup,
+};
+
+/// Name of the generated mcfunction which removes everything the datapack has added to a world
+pub const UNINSTALL_FUNCTION: &str = "uninstall";
+
+/// Generate a function which undoes the datapack's setup, so that it can be removed from a world without leaving anything behind
+///
+/// Every objective in `setup` is removed, along with every macro argument compound in storage and every tag given by any of the datapack's functions. If `pack_id` is given, the datapack then disables itself so that its tick functions stop running
+pub fn uninstall(datapack: &DataPack, setup: &Setup, pack_id: Option<&str>) -> MCFunction {
+    let mut uninstall = MCFunction::new();
+
+    for id in setup.objectives() {
+        uninstall.new_command(ScoreboardCommand::ObjectivesRemove { id: id.to_owned() }.into());
+    }
+
+    // Sets keep the output the same between compilations, whatever order the functions are stored in
+    let mut storages = BTreeSet::new();
+    let mut tags = BTreeSet::new();
+    let functions = datapack
+        .pub_namespace
+        .functions
+        .values()
+        .chain(datapack.private_namespace.functions.values());
+    for function in functions {
+        for command in function.commands() {
+            find_data(command, &mut storages, &mut tags);
+        }
+    }
+
+    for (storage, path) in storages {
+        uninstall.new_command(Command::DataRemoveStorage { storage, path });
+    }
+    for tag in tags {
+        uninstall.new_command(Command::Tag {
+            target: CommandTarget::from(Selector::new(SelectorVariable::AllPlayers)),
+            action: TagAction::Remove,
+            tag,
+        });
+    }
+
+    if let Some(pack_id) = pack_id {
+        uninstall.new_command(Command::DatapackDisable {
+            pack: quote(pack_id),
+        });
+    }
+
+    return uninstall;
+
+    /// Record the storage compounds and tags a command writes to, including those of commands run by `execute`
+    fn find_data(
+        command: &Command,
+        storages: &mut BTreeSet<(String, String)>,
+        tags: &mut BTreeSet<String>,
+    ) {
+        match command {
+            Command::FunctionWithStorage { storage, path, .. } => {
+                storages.insert((storage.clone(), path.clone()));
+            }
+            Command::Tag {
+                action: TagAction::Add,
+                tag,
+                ..
+            } => {
+                tags.insert(tag.clone());
+            }
+            Command::Execute { command, .. } => find_data(command, storages, tags),
+            _ => (),
+        }
+    }
+
+    /// Quote a string argument of a command
+    fn quote(string: &str) -> String {
+        format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
@@ -0,0 +1,142 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::error::CompileError;
+
+/// A version of Minecraft Java Edition which datapacks can be compiled for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TargetVersion {
+    /// The `x` in `1.x.y`
+    minor: u32,
+
+    /// The `y` in `1.x.y`
+    patch: u32,
+}
+
+/// The latest patch of each supported minor version, starting from 1.13
+const LATEST_PATCHES: [u32; 9] = [2, 4, 2, 5, 1, 2, 4, 6, 8];
+
+impl TargetVersion {
+    pub const fn new(minor: u32, patch: u32) -> TargetVersion {
+        TargetVersion { minor, patch }
+    }
+
+    /// The oldest supported version
+    pub const OLDEST: TargetVersion = TargetVersion::new(13, 0);
+
+    /// The newest supported version
+    pub const LATEST: TargetVersion = TargetVersion::new(21, 8);
+
+    /// The `pack_format` in `pack.mcmeta` for this version
+    pub fn pack_format(&self) -> u32 {
+        match (self.minor, self.patch) {
+            (13..=14, _) => 4,
+            (15, _) | (16, 0..=1) => 5,
+            (16, _) => 6,
+            (17, _) => 7,
+            (18, 0..=1) => 8,
+            (18, _) => 9,
+            (19, 0..=3) => 10,
+            (19, _) => 12,
+            (20, 0..=1) => 15,
+            (20, 2) => 18,
+            (20, 3..=4) => 26,
+            (20, _) => 41,
+            (21, 0..=1) => 48,
+            (21, 2..=3) => 57,
+            (21, 4) => 61,
+            (21, 5) => 71,
+            (21, 6) => 80,
+            _ => 81,
+        }
+    }
+
+    /// Name of the folders holding functions, both in namespaces and in `tags`. Made singular in 1.21
+    pub fn function_folder(&self) -> &'static str {
+        if self.minor >= 21 {
+            "function"
+        } else {
+            "functions"
+        }
+    }
+
+    /// Name of the folder in a namespace holding advancements. Made singular in 1.21
+    pub fn advancement_folder(&self) -> &'static str {
+        if self.minor >= 21 {
+            "advancement"
+        } else {
+            "advancements"
+        }
+    }
+
+    /// The longest allowed objective name, if there is a limit. Removed in 1.18
+    pub fn max_objective_len(&self) -> Option<usize> {
+        if self.minor >= 18 {
+            None
+        } else {
+            Some(16)
+        }
+    }
+
+    /// The longest allowed fake player name, if there is a limit. Removed in 1.18
+    pub fn max_fake_player_len(&self) -> Option<usize> {
+        if self.minor >= 18 {
+            None
+        } else {
+            Some(40)
+        }
+    }
+
+    /// Whether the `schedule` command exists. Added in 1.14
+    pub fn has_schedule(&self) -> bool {
+        self.minor >= 14
+    }
+
+    /// Whether functions can take macro arguments. Added in 1.20.2
+    pub fn has_macros(&self) -> bool {
+        *self >= TargetVersion::new(20, 2)
+    }
+}
+
+impl Default for TargetVersion {
+    fn default() -> Self {
+        TargetVersion::OLDEST
+    }
+}
+
+impl FromStr for TargetVersion {
+    type Err = CompileError;
+
+    /// Parse a version such as `1.20` or `1.20.4`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unsupported = || CompileError::UnsupportedTargetVersion {
+            version: s.to_owned(),
+        };
+
+        let mut parts = s.split('.').map(|part| part.parse::<u32>());
+        let (major, minor, patch) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(major)), Some(Ok(minor)), None, None) => (major, minor, 0),
+            (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch)), None) => (major, minor, patch),
+            _ => return Err(unsupported()),
+        };
+
+        let version = TargetVersion::new(minor, patch);
+        if major != 1 || version < TargetVersion::OLDEST || version > TargetVersion::LATEST {
+            return Err(unsupported());
+        }
+        if patch > LATEST_PATCHES[(minor - TargetVersion::OLDEST.minor) as usize] {
+            return Err(unsupported());
+        }
+        Ok(version)
+    }
+}
+
+impl Display for TargetVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.patch == 0 {
+            write!(f, "1.{}", self.minor)
+        } else {
+            write!(f, "1.{}.{}", self.minor, self.patch)
+        }
+    }
+}